@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+drop index if exists idx_identities_user_id;
+drop table if exists identities;
\ No newline at end of file
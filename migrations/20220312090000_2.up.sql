@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+create table identities
+(
+    provider   varchar(64)  not null,
+    subject    varchar(255) not null,
+    user_id    uuid         not null references users (id) on delete cascade,
+    email      varchar(320),
+    created_at timestamptz,
+    primary key (provider, subject)
+);
+
+create index idx_identities_user_id on identities using btree (user_id);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+drop table if exists recovery_codes;
+drop table if exists user_mfa;
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- Add up migration script here
+
+create table user_mfa
+(
+    user_id    uuid        not null primary key references users (id) on delete cascade,
+    secret     bytea       not null,
+    enabled    boolean     not null default false,
+    created_at timestamptz,
+    updated_at timestamptz
+);
+
+create table recovery_codes
+(
+    user_id   uuid        not null references users (id) on delete cascade,
+    code_hash varchar(64) not null,
+    primary key (user_id, code_hash)
+);
\ No newline at end of file
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+alter table users
+    drop column if exists deleted_at,
+    drop column if exists status;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+
+alter table users
+    add column status     smallint not null default 0,
+    add column deleted_at timestamptz;
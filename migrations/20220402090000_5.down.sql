@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+drop index if exists idx_api_keys_owner_id;
+drop table if exists api_keys;
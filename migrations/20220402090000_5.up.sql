@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+create table api_keys
+(
+    id         uuid          not null primary key,
+    owner_id   uuid          not null references users (id) on delete cascade,
+    name       varchar(64)   not null,
+    scopes     varchar(1024) not null,
+    salt       bytea         not null,
+    key_hash   varchar(64)   not null,
+    created_at timestamptz   not null,
+    expires_at timestamptz,
+    revoked_at timestamptz
+);
+
+create index idx_api_keys_owner_id on api_keys using btree (owner_id);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+alter table users
+    drop column if exists service_account;
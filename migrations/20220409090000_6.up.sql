@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+alter table users
+    add column service_account boolean not null default false;
@@ -0,0 +1,188 @@
+use tracing::warn;
+
+use crate::api_key::json::api_key::CachedApiKey;
+use crate::api_key::key;
+use crate::api_key::repo::ApiKeyRepository;
+use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::repo::AuthRepository;
+use crate::core::config::Config;
+use crate::core::error::AppError;
+use crate::user::json::user::UserStatus;
+use crate::user::repo::UserRepository;
+
+pub mod v1;
+
+/// A revoked key, or a changed role of its owner, takes effect within this
+/// time on the other replicas.
+fn get_api_key_cache_seconds() -> usize {
+    60
+}
+
+/// Verifies an api key, the claims are the owner's limited to the scopes of
+/// the key. The key and its owner are cached in redis, so the database
+/// isn't hit on every request.
+pub async fn verify_api_key(
+    api_key_repo: &impl ApiKeyRepository,
+    user_repo: &impl UserRepository,
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    raw_key: &str,
+) -> Result<Principal, AppError> {
+    let (id, secret) = key::parse(raw_key).ok_or(AppError::AuthorizeFailed)?;
+
+    let cached = match auth_repo.get_cached_api_key(&id) {
+        Some(cached) => cached,
+        None => {
+            let api_key = api_key_repo
+                .get(&id)
+                .await?
+                .ok_or(AppError::AuthorizeFailed)?;
+            let owner = user_repo
+                .get(&api_key.owner_id)
+                .await?
+                .ok_or(AppError::AuthorizeFailed)?;
+
+            let cached = CachedApiKey {
+                api_key,
+                role: owner.role,
+                status: owner.status,
+                service_account: owner.service_account,
+            };
+            if let Err(e) = auth_repo.cache_api_key(&cached, get_api_key_cache_seconds()) {
+                warn!("api key {} isn't cached: {}", id, e);
+            }
+            cached
+        }
+    };
+
+    let api_key = &cached.api_key;
+    if !key::verify_secret(api_key, secret) {
+        return Err(AppError::AuthorizeFailed);
+    }
+    if !api_key.is_active() {
+        return Err(AppError::TokenIsExpired);
+    }
+    if UserStatus::from(cached.status) != UserStatus::Active
+        || auth_repo.is_user_blocked(api_key.owner_id)
+    {
+        return Err(AppError::UserDisabled);
+    }
+
+    let mut claims = Claims::new(
+        api_key.owner_id.to_string(),
+        api_key
+            .expires_at
+            .map_or(usize::MAX, |expires_at| expires_at.timestamp() as usize),
+        cached.role as u8,
+        api_key.id.to_string(),
+    );
+    claims.iss = config.jwt_issuer.clone();
+    claims.aud = config.jwt_audience.clone();
+    claims.scopes = Some(api_key.permissions());
+
+    // A key of a service account yields `Principal::Service`.
+    if cached.service_account {
+        Ok(Principal::Service(claims))
+    } else {
+        Ok(Principal::ApiKey(claims))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::api_key::json::api_key::ApiKey;
+    use crate::api_key::repo::MockApiKeyRepository;
+    use crate::auth::repo::MockAuthRepository;
+    use crate::auth::role::Permission;
+    use crate::user::repo::MockUserRepository;
+
+    use super::*;
+
+    fn cached_api_key(id: Uuid, secret: &str, status: UserStatus) -> CachedApiKey {
+        let salt = key::generate_salt();
+
+        CachedApiKey {
+            api_key: ApiKey {
+                id,
+                owner_id: Uuid::new_v4(),
+                name: "batch".to_string(),
+                scopes: serde_json::to_string(&vec![Permission::ReadUsers]).unwrap(),
+                key_hash: key::hash_secret(&salt, secret),
+                salt,
+                created_at: chrono::Utc::now(),
+                expires_at: None,
+                revoked_at: None,
+            },
+            role: 1,
+            status: status.into(),
+            service_account: true,
+        }
+    }
+
+    #[test]
+    fn it_can_verify_cached_api_key() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let id = Uuid::new_v4();
+        let (secret, raw_key) = key::generate(&id);
+        let cached = cached_api_key(id, secret.as_str(), UserStatus::Active);
+
+        let api_key_mock_repo = MockApiKeyRepository::new();
+        let user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo
+            .expect_get_cached_api_key()
+            .returning(move |_| Some(cached.clone()));
+        auth_mock_repo.expect_is_user_blocked().returning(|_| false);
+
+        let principal = runtime
+            .block_on(verify_api_key(
+                &api_key_mock_repo,
+                &user_mock_repo,
+                &auth_mock_repo,
+                &config,
+                raw_key.as_str(),
+            ))
+            .unwrap();
+
+        assert!(matches!(principal, Principal::Service(_)));
+        assert_eq!(principal.claims().jti, id.to_string());
+        assert_eq!(principal.claims().scopes, Some(vec![Permission::ReadUsers]));
+    }
+
+    #[test]
+    fn it_cannot_verify_api_key_with_wrong_secret_or_owner() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let id = Uuid::new_v4();
+        let (secret, raw_key) = key::generate(&id);
+        let disabled = cached_api_key(id, secret.as_str(), UserStatus::Disabled);
+
+        let api_key_mock_repo = MockApiKeyRepository::new();
+        let user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo
+            .expect_get_cached_api_key()
+            .returning(move |_| Some(disabled.clone()));
+        auth_mock_repo.expect_is_user_blocked().returning(|_| false);
+
+        let wrong_key = format!("gw_{}_wrong", id.to_simple());
+        let verify = |raw_key: &str| {
+            runtime.block_on(verify_api_key(
+                &api_key_mock_repo,
+                &user_mock_repo,
+                &auth_mock_repo,
+                &config,
+                raw_key,
+            ))
+        };
+
+        assert_eq!(verify(wrong_key.as_str()).unwrap_err(), AppError::AuthorizeFailed);
+        assert_eq!(verify(raw_key.as_str()).unwrap_err(), AppError::UserDisabled);
+    }
+}
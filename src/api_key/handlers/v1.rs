@@ -0,0 +1,318 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::{Environment, WebResult};
+use crate::api_key::json::api_key::{ApiKey, ApiKeyResponse, CreatedApiKeyResponse};
+use crate::api_key::json::request::{CreateApiKeyRequest, ListApiKeysQuery};
+use crate::api_key::key;
+use crate::api_key::repo::ApiKeyRepository;
+use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::repo::AuthRepository;
+use crate::auth::role::{Permission, Role};
+use crate::core::audit::audit;
+use crate::core::error::{AppError, FieldError};
+use crate::core::middlewares::authorization::Access;
+use crate::user::json::user::UserStatus;
+use crate::user::repo::UserRepository;
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_EXPIRES_IN_DAYS: u32 = 3650;
+
+pub async fn create_api_key_handler(
+    principal: Principal,
+    req: CreateApiKeyRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    create_api_key(env.api_key_repo, env.user_repo, principal.claims(), req)
+        .await
+        .map(|response| {
+            warp::reply::with_status(warp::reply::json(&response), StatusCode::CREATED)
+        })
+        .map_err(warp::reject::custom)
+}
+
+/// Creates a key of the caller, or of another owner such as a service
+/// account. The scopes can't exceed the permissions of the owner's role.
+async fn create_api_key(
+    api_key_repo: Arc<impl ApiKeyRepository>,
+    user_repo: Arc<impl UserRepository>,
+    claims: &Claims,
+    req: CreateApiKeyRequest,
+) -> Result<CreatedApiKeyResponse, AppError> {
+    let owner_id = owner_id(claims, req.owner_id)?;
+
+    let owner = user_repo
+        .get(&owner_id)
+        .await?
+        .filter(|owner| UserStatus::from(owner.status) == UserStatus::Active)
+        .ok_or(AppError::UserNotExist)?;
+    validate(&req, Role::try_from(owner.role)?)?;
+
+    let id = Uuid::new_v4();
+    let (secret, raw_key) = key::generate(&id);
+    let salt = key::generate_salt();
+    let now = chrono::Utc::now();
+
+    let api_key = ApiKey {
+        id,
+        owner_id,
+        name: req.name,
+        scopes: serde_json::to_string(&req.scopes).map_err(|_| AppError::DatabaseError)?,
+        key_hash: key::hash_secret(&salt, secret.as_str()),
+        salt,
+        created_at: now,
+        expires_at: req
+            .expires_in_days
+            .map(|days| now + chrono::Duration::days(days as i64)),
+        revoked_at: None,
+    };
+    api_key_repo.create(&api_key).await?;
+
+    audit("api_key_created", id.to_string().as_str(), Some(claims.sub.as_str()), None);
+
+    Ok(CreatedApiKeyResponse {
+        key: raw_key,
+        api_key: api_key.into(),
+    })
+}
+
+pub async fn list_api_keys_handler(
+    query: ListApiKeysQuery,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    list_api_keys(env.api_key_repo, principal.claims(), query)
+        .await
+        .map(|api_keys| warp::reply::json(&api_keys))
+        .map_err(warp::reject::custom)
+}
+
+async fn list_api_keys(
+    api_key_repo: Arc<impl ApiKeyRepository>,
+    claims: &Claims,
+    query: ListApiKeysQuery,
+) -> Result<Vec<ApiKeyResponse>, AppError> {
+    let owner_id = owner_id(claims, query.owner_id)?;
+
+    let api_keys = api_key_repo.list(&owner_id).await?;
+
+    Ok(api_keys.into_iter().map(ApiKeyResponse::from).collect())
+}
+
+pub async fn revoke_api_key_handler(
+    id: Uuid,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    revoke_api_key(env.api_key_repo, env.auth_repo, principal.claims(), &id)
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+/// The key stops working at once, its cached copy is dropped too.
+async fn revoke_api_key(
+    api_key_repo: Arc<impl ApiKeyRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    id: &Uuid,
+) -> Result<(), AppError> {
+    let api_key = api_key_repo
+        .get(id)
+        .await?
+        .ok_or(AppError::ApiKeyNotExist)?;
+    owner_id(claims, Some(api_key.owner_id))?;
+
+    if !api_key_repo.revoke(id).await? {
+        return Err(AppError::ApiKeyNotExist);
+    }
+    auth_repo
+        .evict_api_key(id)
+        .map_err(|_| AppError::DatabaseError)?;
+
+    audit("api_key_revoked", id.to_string().as_str(), Some(claims.sub.as_str()), None);
+    Ok(())
+}
+
+/// Keys of another owner are managed with the `manage_users` permission.
+fn owner_id(claims: &Claims, owner_id: Option<Uuid>) -> Result<Uuid, AppError> {
+    let caller_id = Uuid::parse_str(claims.sub.as_str()).map_err(|_| AppError::AuthorizeFailed)?;
+
+    match owner_id {
+        Some(owner_id) if owner_id != caller_id => {
+            Access::permission(Permission::ManageUsers).check(claims)?;
+            Ok(owner_id)
+        }
+        _ => Ok(caller_id),
+    }
+}
+
+fn validate(req: &CreateApiKeyRequest, owner_role: Role) -> Result<(), AppError> {
+    let mut errors = vec![];
+
+    let length = req.name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH {
+        errors.push(FieldError::new(
+            "name",
+            format!("must be 1 to {} characters.", MAX_NAME_LENGTH),
+        ));
+    }
+    if !req.scopes.iter().all(|scope| owner_role.has_permission(*scope)) {
+        errors.push(FieldError::new("scopes", "must be permissions of the owner's role."));
+    }
+    if let Some(days) = req.expires_in_days {
+        if days == 0 || days > MAX_EXPIRES_IN_DAYS {
+            errors.push(FieldError::new(
+                "expires_in_days",
+                format!("must be 1 to {}.", MAX_EXPIRES_IN_DAYS),
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::ValidationFailed(errors))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::api_key::repo::MockApiKeyRepository;
+    use crate::auth::repo::MockAuthRepository;
+    use crate::user::json::user::SimpleUser;
+    use crate::user::repo::MockUserRepository;
+
+    use super::*;
+
+    fn owner(id: Uuid, role: Role) -> SimpleUser {
+        SimpleUser {
+            id,
+            name: "batch".to_string(),
+            role: role.into(),
+            created_at: None,
+            updated_at: None,
+            status: 0,
+            deleted_at: None,
+            service_account: false,
+        }
+    }
+
+    fn request(scopes: Vec<Permission>, owner_id: Option<Uuid>) -> CreateApiKeyRequest {
+        CreateApiKeyRequest {
+            name: "nightly export".to_string(),
+            scopes,
+            expires_in_days: Some(30),
+            owner_id,
+        }
+    }
+
+    #[test]
+    fn it_can_create_api_key() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let user_id = Uuid::new_v4();
+        let claims = Claims::new(user_id.to_string(), 0, Role::Staff as u8, "session".to_string());
+
+        let mut api_key_mock_repo = MockApiKeyRepository::new();
+        let mut user_mock_repo = MockUserRepository::new();
+
+        user_mock_repo
+            .expect_get()
+            .returning(move |_| Ok(Some(owner(user_id, Role::Staff))));
+        api_key_mock_repo
+            .expect_create()
+            .withf(move |api_key| api_key.owner_id == user_id && !api_key.key_hash.is_empty())
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let response = runtime
+            .block_on(create_api_key(
+                Arc::new(api_key_mock_repo),
+                Arc::new(user_mock_repo),
+                &claims,
+                request(vec![Permission::ReadUsers], None),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            key::parse(response.key.as_str()).map(|(id, _)| id),
+            Some(response.api_key.id)
+        );
+        assert_eq!(response.api_key.scopes, vec![Permission::ReadUsers]);
+    }
+
+    #[test]
+    fn it_cannot_create_api_key_beyond_owner_role() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let user_id = Uuid::new_v4();
+        let claims = Claims::new(user_id.to_string(), 0, Role::Staff as u8, "session".to_string());
+
+        let mut api_key_mock_repo = MockApiKeyRepository::new();
+        let mut user_mock_repo = MockUserRepository::new();
+
+        user_mock_repo
+            .expect_get()
+            .returning(move |_| Ok(Some(owner(user_id, Role::Staff))));
+        api_key_mock_repo.expect_create().never();
+
+        let response = runtime.block_on(create_api_key(
+            Arc::new(api_key_mock_repo),
+            Arc::new(user_mock_repo),
+            &claims,
+            request(vec![Permission::ManageUsers], None),
+        ));
+
+        assert!(matches!(response, Err(AppError::ValidationFailed(_))));
+    }
+
+    #[test]
+    fn it_cannot_create_api_key_expiring_too_late() {
+        let mut req = request(vec![Permission::ReadUsers], None);
+        req.expires_in_days = Some(u32::MAX);
+
+        assert!(matches!(validate(&req, Role::Staff), Err(AppError::ValidationFailed(_))));
+    }
+
+    #[test]
+    fn it_cannot_manage_api_keys_of_other_owner() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let claims = Claims::new(
+            Uuid::new_v4().to_string(),
+            0,
+            Role::Staff as u8,
+            "session".to_string(),
+        );
+
+        let mut api_key_mock_repo = MockApiKeyRepository::new();
+        let auth_mock_repo = MockAuthRepository::new();
+
+        api_key_mock_repo.expect_get().returning(|id| {
+            Ok(Some(ApiKey {
+                id: *id,
+                owner_id: Uuid::new_v4(),
+                name: "batch".to_string(),
+                scopes: "[]".to_string(),
+                salt: vec![],
+                key_hash: String::new(),
+                created_at: chrono::Utc::now(),
+                expires_at: None,
+                revoked_at: None,
+            }))
+        });
+        api_key_mock_repo.expect_revoke().never();
+
+        let response = runtime.block_on(revoke_api_key(
+            Arc::new(api_key_mock_repo),
+            Arc::new(auth_mock_repo),
+            &claims,
+            &Uuid::new_v4(),
+        ));
+
+        assert_eq!(response.unwrap_err(), AppError::Forbidden);
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::auth::role::Permission;
+
+/// A row of `api_keys`, only a salted hash of the secret is stored.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    /// Json array of the permissions granted to the key.
+    pub scopes: String,
+    pub salt: Vec<u8>,
+    pub key_hash: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ApiKey {
+    pub fn permissions(&self) -> Vec<Permission> {
+        serde_json::from_str(self.scopes.as_str()).unwrap_or_default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none()
+            && self
+                .expires_at
+                .map_or(true, |expires_at| expires_at > chrono::Utc::now())
+    }
+}
+
+/// An api key and the role and status of its owner, as cached in redis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedApiKey {
+    pub api_key: ApiKey,
+    pub role: i16,
+    pub status: i16,
+    #[serde(default)]
+    pub service_account: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(api_key: ApiKey) -> Self {
+        Self {
+            scopes: api_key.permissions(),
+            id: api_key.id,
+            owner_id: api_key.owner_id,
+            name: api_key.name,
+            created_at: api_key.created_at,
+            expires_at: api_key.expires_at,
+            revoked_at: api_key.revoked_at,
+        }
+    }
+}
+
+/// The key is shown once, it can't be recovered afterwards.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
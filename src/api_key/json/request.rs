@@ -0,0 +1,19 @@
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::role::Permission;
+
+/// A key of another owner, e.g. a service account, needs the
+/// `manage_users` permission.
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Permission>,
+    pub expires_in_days: Option<u32>,
+    pub owner_id: Option<Uuid>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListApiKeysQuery {
+    pub owner_id: Option<Uuid>,
+}
@@ -0,0 +1,15 @@
+use sea_query::Iden;
+
+#[derive(Iden)]
+pub enum ApiKeys {
+    Table,
+    Id,
+    OwnerId,
+    Name,
+    Scopes,
+    Salt,
+    KeyHash,
+    CreatedAt,
+    ExpiresAt,
+    RevokedAt,
+}
@@ -0,0 +1,77 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::api_key::json::api_key::ApiKey;
+use crate::auth::handlers::create_random_token;
+use crate::core::util::constant_time_eq;
+
+// Keys look like `gw_<id>_<secret>`, the id finds the row without a scan.
+const KEY_PREFIX: &str = "gw";
+const SALT_LENGTH: usize = 16;
+
+/// Returns the secret of a new key and the full key given to the client.
+pub fn generate(id: &Uuid) -> (String, String) {
+    let secret = create_random_token();
+    let key = format!("{}_{}_{}", KEY_PREFIX, id.to_simple(), secret);
+    (secret, key)
+}
+
+pub fn parse(key: &str) -> Option<(Uuid, &str)> {
+    let mut parts = key.trim().splitn(3, '_');
+
+    if parts.next()? != KEY_PREFIX {
+        return None;
+    }
+    let id = Uuid::parse_str(parts.next()?).ok()?;
+    let secret = parts.next().filter(|secret| !secret.is_empty())?;
+
+    Some((id, secret))
+}
+
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// The secret is 32 random bytes, a salted sha256 is enough and keeps the
+/// check cheap on every request.
+pub fn hash_secret(salt: &[u8], secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(secret.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn verify_secret(api_key: &ApiKey, secret: &str) -> bool {
+    constant_time_eq(
+        hash_secret(&api_key.salt, secret).as_bytes(),
+        api_key.key_hash.as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_parse_generated_key() {
+        let id = Uuid::new_v4();
+        let (secret, key) = generate(&id);
+
+        assert_eq!(parse(key.as_str()), Some((id, secret.as_str())));
+        assert_eq!(parse("gw_not-an-id_secret"), None);
+        assert_eq!(parse(format!("gw_{}_", id.to_simple()).as_str()), None);
+    }
+
+    #[test]
+    fn it_can_salt_secret_hash() {
+        let secret = "secret";
+
+        assert_ne!(
+            hash_secret(&generate_salt(), secret),
+            hash_secret(&generate_salt(), secret)
+        );
+    }
+}
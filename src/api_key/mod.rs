@@ -0,0 +1,5 @@
+pub mod handlers;
+pub mod json;
+pub mod key;
+pub mod repo;
+pub mod route;
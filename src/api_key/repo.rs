@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sea_query::{Expr, Order, PostgresQueryBuilder, Query};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::api_key::json::api_key::ApiKey;
+use crate::api_key::json::table::ApiKeys;
+use crate::core::error::AppError;
+
+#[async_trait]
+pub trait ApiKeyRepository {
+    async fn create(&self, api_key: &ApiKey) -> Result<(), AppError>;
+
+    async fn get(&self, id: &Uuid) -> Result<Option<ApiKey>, AppError>;
+
+    /// Keys of the owner, newest first, revoked and expired keys included.
+    async fn list(&self, owner_id: &Uuid) -> Result<Vec<ApiKey>, AppError>;
+
+    /// Returns `false` when the key doesn't exist or was revoked before.
+    async fn revoke(&self, id: &Uuid) -> Result<bool, AppError>;
+}
+
+#[derive(Clone)]
+pub struct PostgresApiKeyRepository {
+    connection_pool: Arc<Pool<Postgres>>,
+}
+
+impl PostgresApiKeyRepository {
+    pub fn new(pool: Arc<Pool<Postgres>>) -> Self {
+        Self {
+            connection_pool: pool,
+        }
+    }
+
+    fn columns() -> Vec<ApiKeys> {
+        vec![
+            ApiKeys::Id,
+            ApiKeys::OwnerId,
+            ApiKeys::Name,
+            ApiKeys::Scopes,
+            ApiKeys::Salt,
+            ApiKeys::KeyHash,
+            ApiKeys::CreatedAt,
+            ApiKeys::ExpiresAt,
+            ApiKeys::RevokedAt,
+        ]
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for PostgresApiKeyRepository {
+    async fn create(&self, api_key: &ApiKey) -> Result<(), AppError> {
+        let sql = Query::insert()
+            .into_table(ApiKeys::Table)
+            .columns(Self::columns())
+            .values_panic(vec![
+                api_key.id.into(),
+                api_key.owner_id.into(),
+                api_key.name.as_str().into(),
+                api_key.scopes.as_str().into(),
+                api_key.salt.clone().into(),
+                api_key.key_hash.as_str().into(),
+                api_key.created_at.into(),
+                api_key.expires_at.into(),
+                api_key.revoked_at.into(),
+            ])
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query(sql.as_str())
+            .execute(&*self.connection_pool)
+            .await
+            .map(|_| ())
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn get(&self, id: &Uuid) -> Result<Option<ApiKey>, AppError> {
+        let sql = Query::select()
+            .columns(Self::columns())
+            .from(ApiKeys::Table)
+            .and_where(Expr::col(ApiKeys::Id).eq(id.to_string()))
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, ApiKey>(sql.as_str())
+            .fetch_optional(&*self.connection_pool)
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn list(&self, owner_id: &Uuid) -> Result<Vec<ApiKey>, AppError> {
+        let sql = Query::select()
+            .columns(Self::columns())
+            .from(ApiKeys::Table)
+            .and_where(Expr::col(ApiKeys::OwnerId).eq(owner_id.to_string()))
+            .order_by(ApiKeys::CreatedAt, Order::Desc)
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, ApiKey>(sql.as_str())
+            .fetch_all(&*self.connection_pool)
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn revoke(&self, id: &Uuid) -> Result<bool, AppError> {
+        let sql = Query::update()
+            .table(ApiKeys::Table)
+            .values(vec![(ApiKeys::RevokedAt, chrono::Utc::now().into())])
+            .and_where(Expr::col(ApiKeys::Id).eq(id.to_string()))
+            .and_where(Expr::col(ApiKeys::RevokedAt).is_null())
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query(sql.as_str())
+            .execute(&*self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() == 1)
+            .map_err(|_| AppError::DatabaseError)
+    }
+}
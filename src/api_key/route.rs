@@ -0,0 +1,38 @@
+use uuid::Uuid;
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+
+use crate::api_key::handlers::v1::{
+    create_api_key_handler, list_api_keys_handler, revoke_api_key_handler,
+};
+use crate::core::middlewares::authorization::{authenticated, AuthMethods};
+use crate::core::middlewares::with_env::with_env;
+use crate::Environment;
+
+pub fn routes(env: Environment) -> BoxedFilter<(impl Reply,)> {
+    // Keys are managed with a session only, a leaked key can't mint new ones.
+    let create_api_key_route = warp::path!("api" / "v1" / "api-keys")
+        .and(warp::post())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(create_api_key_handler);
+
+    let list_api_keys_route = warp::path!("api" / "v1" / "api-keys")
+        .and(warp::get())
+        .and(warp::query())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env.clone()))
+        .and_then(list_api_keys_handler);
+
+    let revoke_api_key_route = warp::path!("api" / "v1" / "api-keys" / Uuid)
+        .and(warp::delete())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env))
+        .and_then(revoke_api_key_handler);
+
+    let routes = create_api_key_route
+        .or(list_api_keys_route)
+        .or(revoke_api_key_route);
+    routes.boxed()
+}
@@ -1,18 +1,138 @@
-use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::RngCore;
+use uuid::Uuid;
 
 use crate::auth::json::claims::Claims;
+use crate::auth::json::mfa::MfaChallenge;
+use crate::auth::json::session::{Session, SessionMetadata};
+use crate::auth::json::token::{RefreshToken, TokenPair};
+use crate::auth::keys::KeyStore;
+use crate::auth::repo::AuthRepository;
+use crate::core::config::Config;
+use crate::core::error::AppError;
+use crate::user::json::user::UserStatus;
 
+pub mod oidc;
 pub mod v1;
 
-fn create_token(claims: Claims, secret_key: &str) -> String {
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret_key.as_bytes()),
-    );
-    token.unwrap()
+fn create_token(claims: Claims, key_store: &KeyStore) -> String {
+    key_store.sign(&claims).unwrap()
+}
+
+/// Lifetime of a session and of its refresh tokens.
+fn get_expired_seconds(config: &Config) -> usize {
+    config.session_expired_seconds
+}
+
+fn get_access_expired_seconds(config: &Config) -> usize {
+    config.access_token_expired_seconds
+}
+
+/// Lifetime of the state of an oidc login, the user has to come back from
+/// the provider within it.
+fn get_oidc_state_expired_seconds(config: &Config) -> usize {
+    config.oidc_state_expired_seconds
+}
+
+/// Time a user has to enter the second factor after the password.
+fn get_mfa_challenge_expired_seconds(config: &Config) -> usize {
+    config.mfa_challenge_expired_seconds
+}
+
+/// 32 random bytes, used for refresh tokens, oidc states, mfa tokens and
+/// password reset tokens.
+pub fn create_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Only active users get a session or a renewed token.
+fn check_status(status: i16) -> Result<(), AppError> {
+    match UserStatus::from(status) {
+        UserStatus::Active => Ok(()),
+        _ => Err(AppError::UserDisabled),
+    }
+}
+
+/// Creates a session of the authenticated user and issues its tokens.
+fn start_session(
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    key_store: &KeyStore,
+    user_id: Uuid,
+    role: u8,
+    metadata: SessionMetadata,
+) -> Result<TokenPair, AppError> {
+    let session = Session::new(user_id, metadata);
+
+    auth_repo
+        .create(&session, get_expired_seconds(config))
+        .map_err(|_| AppError::TokenNotExist)?;
+
+    issue_tokens(
+        auth_repo,
+        config,
+        key_store,
+        user_id,
+        role,
+        session.id.as_str(),
+    )
+}
+
+/// Keeps a login which waits for its second factor, returns the mfa token.
+fn start_mfa_challenge(
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    challenge: &MfaChallenge,
+) -> Result<String, AppError> {
+    let mfa_token = create_random_token();
+
+    auth_repo
+        .create_mfa_challenge(
+            mfa_token.as_str(),
+            challenge,
+            get_mfa_challenge_expired_seconds(config),
+        )
+        .map_err(|_| AppError::TokenNotExist)?;
+
+    Ok(mfa_token)
 }
 
-fn get_expired_seconds() -> usize {
-    30 * 24 * 60 * 60
+/// Issues a short-lived access token and a new refresh token of the
+/// session `session_id`.
+fn issue_tokens(
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    key_store: &KeyStore,
+    user_id: Uuid,
+    role: u8,
+    session_id: &str,
+) -> Result<TokenPair, AppError> {
+    let expired_at =
+        chrono::Utc::now() + chrono::Duration::seconds(get_access_expired_seconds(config) as i64);
+    let mut claims = Claims::new(
+        user_id.to_string(),
+        expired_at.timestamp() as usize,
+        role,
+        session_id.to_string(),
+    );
+    claims.iss = config.jwt_issuer.clone();
+    claims.aud = config.jwt_audience.clone();
+
+    let refresh_token = create_random_token();
+    auth_repo
+        .create_refresh_token(
+            refresh_token.as_str(),
+            &RefreshToken {
+                session_id: session_id.to_string(),
+                user_id,
+            },
+            get_expired_seconds(config),
+        )
+        .map_err(|_| AppError::TokenNotExist)?;
+
+    Ok(TokenPair {
+        access_token: create_token(claims, key_store),
+        refresh_token,
+    })
 }
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use warp::http::{Response, StatusCode};
+use warp::Reply;
+
+use crate::{Environment, WebResult};
+use crate::auth::handlers::{
+    check_status, create_random_token, get_mfa_challenge_expired_seconds,
+    get_oidc_state_expired_seconds, start_mfa_challenge, start_session,
+};
+use crate::auth::handlers::v1::with_token_cookies;
+use crate::auth::json::mfa::MfaChallenge;
+use crate::auth::json::oidc::{CallbackQuery, IdTokenClaims, OidcState};
+use crate::auth::json::session::SessionMetadata;
+use crate::auth::json::token::LoginResult;
+use crate::auth::keys::KeyStore;
+use crate::auth::oidc::{code_challenge, OidcClient};
+use crate::auth::repo::AuthRepository;
+use crate::core::config::Config;
+use crate::core::error::AppError;
+use crate::user::json::user::SimpleUser;
+use crate::user::repo::UserRepository;
+
+pub async fn oidc_login_handler(provider: String, env: Environment) -> WebResult<impl Reply> {
+    oidc_login(&env.oidc_clients, env.auth_repo, &env.config, provider.as_str())
+        .await
+        .map(|url| redirect_response(url.as_str()).body("").unwrap())
+        .map_err(warp::reject::custom)
+}
+
+/// Stores the state, nonce and PKCE verifier of a new login and returns the
+/// authorization url of the provider.
+async fn oidc_login(
+    clients: &HashMap<String, OidcClient>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    provider: &str,
+) -> Result<String, AppError> {
+    let client = clients.get(provider).ok_or(AppError::ProviderNotExist)?;
+
+    let state = create_random_token();
+    let value = OidcState {
+        provider: provider.to_string(),
+        nonce: create_random_token(),
+        code_verifier: create_random_token(),
+    };
+
+    let url = client
+        .authorization_url(
+            state.as_str(),
+            value.nonce.as_str(),
+            code_challenge(value.code_verifier.as_str()).as_str(),
+        )
+        .await?;
+
+    auth_repo
+        .create_oidc_state(state.as_str(), &value, get_oidc_state_expired_seconds(config))
+        .map_err(|_| AppError::DatabaseError)?;
+
+    Ok(url)
+}
+
+pub async fn oidc_callback_handler(
+    provider: String,
+    query: CallbackQuery,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    let metadata = SessionMetadata {
+        device: Some(provider.clone()),
+        ip: ip.map(|ip| ip.to_string()),
+        user_agent,
+    };
+
+    oidc_callback(
+        &env.oidc_clients,
+        env.user_repo,
+        env.auth_repo,
+        &env.config,
+        &env.key_store,
+        provider.as_str(),
+        query,
+        metadata,
+    )
+    .await
+    .map(|result| {
+        let location = env.config.oidc.post_login_redirect.as_str();
+        match result {
+            LoginResult::Authenticated(tokens) => {
+                with_token_cookies(redirect_response(location), tokens, &env.config)
+                    .body("")
+                    .unwrap()
+            }
+            // The fragment stays in the browser, the client finishes the
+            // login with `/api/v1/login/mfa`.
+            LoginResult::MfaRequired(mfa_token) => {
+                let location = format!(
+                    "{}#mfa_token={}&expires_in={}",
+                    location,
+                    mfa_token,
+                    get_mfa_challenge_expired_seconds(&env.config)
+                );
+                redirect_response(location.as_str()).body("").unwrap()
+            }
+        }
+    })
+    .map_err(warp::reject::custom)
+}
+
+/// Finishes the login, the user linked to the identity is created on its
+/// first login. Users with mfa still have to give their code.
+#[allow(clippy::too_many_arguments)]
+async fn oidc_callback(
+    clients: &HashMap<String, OidcClient>,
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    key_store: &KeyStore,
+    provider: &str,
+    query: CallbackQuery,
+    metadata: SessionMetadata,
+) -> Result<LoginResult, AppError> {
+    let client = clients.get(provider).ok_or(AppError::ProviderNotExist)?;
+
+    let state = auth_repo
+        .take_oidc_state(query.state.as_str())
+        .filter(|state| state.provider == provider)
+        .ok_or(AppError::AuthorizeFailed)?;
+
+    let id_token = client
+        .exchange_code(query.code.as_str(), state.code_verifier.as_str())
+        .await?;
+    let claims = client
+        .validate_id_token(id_token.as_str(), state.nonce.as_str())
+        .await?;
+
+    let user = match user_repo
+        .get_by_identity(provider, claims.sub.as_str())
+        .await?
+    {
+        Some(user) => user,
+        None => {
+            create_external_user(&*user_repo, client.config.role.into(), provider, &claims)
+                .await?
+        }
+    };
+    check_status(user.status)?;
+
+    start_external_session(&*user_repo, &*auth_repo, config, key_store, user, metadata).await
+}
+
+async fn start_external_session(
+    user_repo: &impl UserRepository,
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    key_store: &KeyStore,
+    user: SimpleUser,
+    metadata: SessionMetadata,
+) -> Result<LoginResult, AppError> {
+    let mfa_enabled = user_repo
+        .get_mfa(&user.id)
+        .await?
+        .map_or(false, |mfa| mfa.enabled);
+
+    if mfa_enabled {
+        let challenge = MfaChallenge {
+            user_id: user.id,
+            username: user.name,
+            role: user.role as u8,
+            metadata,
+        };
+        return start_mfa_challenge(auth_repo, config, &challenge).map(LoginResult::MfaRequired);
+    }
+
+    start_session(auth_repo, config, key_store, user.id, user.role as u8, metadata)
+        .map(LoginResult::Authenticated)
+}
+
+/// Names of external users are prefixed by the provider, so they can't
+/// collide with the names of local users.
+fn username(provider: &str, claims: &IdTokenClaims) -> String {
+    let name = claims
+        .preferred_username
+        .as_ref()
+        .or(claims.email.as_ref())
+        .unwrap_or(&claims.sub);
+
+    format!("{}:{}", provider, name).chars().take(64).collect()
+}
+
+fn unique_username(name: &str, claims: &IdTokenClaims) -> String {
+    let suffix = Sha256::digest(claims.sub.as_bytes())
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    format!("{}#{}", name.chars().take(55).collect::<String>(), suffix)
+}
+
+/// Another identity of the provider may already use the name, the subject
+/// is then hashed into it.
+async fn create_external_user(
+    user_repo: &impl UserRepository,
+    role: i16,
+    provider: &str,
+    claims: &IdTokenClaims,
+) -> Result<SimpleUser, AppError> {
+    let name = username(provider, claims);
+    let created = user_repo
+        .create_with_identity(
+            name.as_str(),
+            role,
+            provider,
+            claims.sub.as_str(),
+            claims.email.clone(),
+        )
+        .await;
+
+    match created {
+        Err(AppError::UserAlreadyExist) => {
+            user_repo
+                .create_with_identity(
+                    unique_username(name.as_str(), claims).as_str(),
+                    role,
+                    provider,
+                    claims.sub.as_str(),
+                    claims.email.clone(),
+                )
+                .await
+        }
+        created => created,
+    }
+}
+
+fn redirect_response(location: &str) -> warp::http::response::Builder {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header("location", location)
+}
+
+#[cfg(test)]
+mod test {
+    use jsonwebtoken::Algorithm;
+
+    use crate::auth::repo::MockAuthRepository;
+    use crate::user::json::mfa::Mfa;
+    use crate::user::repo::MockUserRepository;
+
+    use super::*;
+
+    #[test]
+    fn it_cannot_login_with_unknown_provider() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let auth_mock_repo = MockAuthRepository::new();
+        let config = Config::new().unwrap();
+
+        let result = runtime.block_on(oidc_login(
+            &HashMap::new(),
+            Arc::new(auth_mock_repo),
+            &config,
+            "corporate",
+        ));
+
+        assert_eq!(result.unwrap_err(), AppError::ProviderNotExist);
+    }
+
+    #[test]
+    fn it_can_name_external_user() {
+        let claims = IdTokenClaims {
+            sub: "248289761001".to_string(),
+            nonce: None,
+            email: Some("alice@example.com".to_string()),
+            preferred_username: None,
+        };
+
+        assert_eq!(username("corporate", &claims), "corporate:alice@example.com");
+    }
+
+    #[test]
+    fn it_can_create_external_user_with_taken_name() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let claims = IdTokenClaims {
+            sub: "248289761001".to_string(),
+            nonce: None,
+            email: None,
+            preferred_username: Some("alice".to_string()),
+        };
+
+        user_mock_repo
+            .expect_create_with_identity()
+            .withf(|name, _, _, _, _| *name == "corporate:alice")
+            .returning(|_, _, _, _, _| Err(AppError::UserAlreadyExist));
+        user_mock_repo
+            .expect_create_with_identity()
+            .withf(|name, _, _, _, _| name.starts_with("corporate:alice#"))
+            .returning(|name, role, _, _, _| {
+                Ok(SimpleUser {
+                    id: uuid::Uuid::new_v4(),
+                    name: name.to_string(),
+                    role,
+                    created_at: None,
+                    updated_at: None,
+                    status: 0,
+                    deleted_at: None,
+                    service_account: false,
+                })
+            });
+
+        let user = runtime
+            .block_on(create_external_user(&user_mock_repo, 1, "corporate", &claims))
+            .unwrap();
+
+        assert_eq!(user.name, unique_username("corporate:alice", &claims));
+        assert_eq!(user.name.len(), "corporate:alice#".len() + 8);
+    }
+
+    #[test]
+    fn it_can_require_mfa_of_external_user() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+        let user = SimpleUser {
+            id: uuid::Uuid::new_v4(),
+            name: "corporate:alice".to_string(),
+            role: 1,
+            created_at: None,
+            updated_at: None,
+            status: 0,
+            deleted_at: None,
+            service_account: false,
+        };
+
+        user_mock_repo.expect_get_mfa().returning(|user_id| {
+            Ok(Some(Mfa {
+                user_id: *user_id,
+                secret: vec![0; 20],
+                enabled: true,
+            }))
+        });
+        auth_mock_repo
+            .expect_create_mfa_challenge()
+            .withf(|_, challenge, _| challenge.username == "corporate:alice")
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        auth_mock_repo.expect_create().never();
+
+        let result = runtime.block_on(start_external_session(
+            &user_mock_repo,
+            &auth_mock_repo,
+            &config,
+            &key_store,
+            user,
+            SessionMetadata::default(),
+        ));
+
+        assert!(matches!(result, Ok(LoginResult::MfaRequired(_))));
+    }
+}
@@ -1,132 +1,517 @@
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use chrono::Utc;
+use tracing::warn;
 use uuid::Uuid;
+use warp::http::response::Builder;
+use warp::http::{Response, StatusCode};
 use warp::Reply;
 
 use crate::{Environment, WebResult};
-use crate::auth::handlers::{create_token, get_expired_seconds};
+use crate::auth::handlers::{
+    check_status, get_access_expired_seconds, get_expired_seconds,
+    get_mfa_challenge_expired_seconds, issue_tokens, start_mfa_challenge, start_session,
+};
 use crate::auth::json::claims::Claims;
+use crate::auth::json::mfa::{MfaChallenge, MfaChallengeResponse, MfaLoginRequest};
+use crate::auth::json::principal::Principal;
 use crate::auth::json::request::AuthRequest;
+use crate::auth::json::session::{SessionMetadata, SessionResponse};
+use crate::auth::json::token::{LoginResult, TokenPair};
+use crate::auth::keys::KeyStore;
+use crate::auth::lockout::{check_lockout, record_failure, record_success, user_subject};
 use crate::auth::repo::AuthRepository;
+use crate::core::audit::audit;
 use crate::core::config::Config;
+use crate::core::cookie::CookieConfig;
 use crate::core::error::AppError;
+use crate::core::middlewares::csrf::create_csrf_token;
+use crate::core::middlewares::rate_limit::rate_limit_subject;
+use crate::core::util::{hash_password, needs_rehash, verify_password};
+use crate::proxy::json::config::RateLimitKey;
+use crate::user::handlers::mfa::verify_second_factor;
+use crate::user::json::user::UserStatus;
 use crate::user::repo::UserRepository;
 
-pub async fn login_handler(req: AuthRequest, env: Environment) -> WebResult<impl Reply> {
+// Wrong codes a mfa token takes before it's revoked, a new login is needed.
+const MAX_MFA_FAILURES: u32 = 5;
+
+pub async fn login_handler(
+    req: AuthRequest,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    let metadata = SessionMetadata {
+        device: req.device,
+        ip: ip.map(|ip| ip.to_string()),
+        user_agent,
+    };
+
     login(
         env.user_repo,
         env.auth_repo,
         &env.config,
+        &env.key_store,
         req.username.as_str(),
         req.password.as_str(),
+        metadata,
     )
     .await
-    .map(|token| {
-        warp::reply::with_header(
-            "login success",
-            "set-cookie",
-            create_cookie(
-                token.as_str(),
-                chrono::Utc::now() + chrono::Duration::days(30),
-                30 * 24 * 3600,
-            ),
-        )
+    .map(|result| match result {
+        LoginResult::Authenticated(tokens) => {
+            token_response("login success", tokens, &env.config).into_response()
+        }
+        LoginResult::MfaRequired(mfa_token) => warp::reply::json(&MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token,
+            expires_in: get_mfa_challenge_expired_seconds(&env.config),
+        })
+        .into_response(),
     })
     .map_err(warp::reject::custom)
 }
 
+/// Checks the password, users with mfa get a mfa token instead of a session.
 async fn login(
     user_repo: Arc<impl UserRepository>,
     auth_repo: Arc<impl AuthRepository>,
     config: &Config,
+    key_store: &KeyStore,
     username: &str,
     password: &str,
-) -> Result<String, AppError> {
+    metadata: SessionMetadata,
+) -> Result<LoginResult, AppError> {
+    let ip = metadata.ip.clone();
+    check_lockout(&*auth_repo, config, username, ip.as_deref())?;
+
     let user_opt = user_repo.get_by_name(username).await;
 
     match user_opt {
-        Ok(None) => Err(AppError::UserNotExist),
         Err(e) => Err(e),
-        Ok(Some(user)) => {
-            let verify = argon2::verify_encoded(user.password.as_str(), password.as_bytes())
-                .ok()
-                .unwrap_or(false);
-
-            if !verify {
-                return Err(AppError::AuthorizeFailed);
+        Ok(user_opt) => {
+            let verify = match &user_opt {
+                Some(user) => verify_password(user.password.as_str(), password, config),
+                // Hash anyway, an unknown user must take as long as a wrong
+                // password.
+                None => {
+                    let _ = hash_password(password, config);
+                    false
+                }
+            };
+
+            // A deleted user is refused like an unknown one.
+            let user = match user_opt {
+                Some(user) if verify && UserStatus::from(user.status) != UserStatus::Deleted => user,
+                _ => {
+                    record_failure(&*auth_repo, config, username, ip.as_deref()).await;
+                    return Err(AppError::AuthorizeFailed);
+                }
+            };
+
+            check_status(user.status)?;
+
+            let user_id = user.id.unwrap();
+            if needs_rehash(user.password.as_str(), config) {
+                upgrade_password(&*user_repo, config, &user_id, password).await;
+            }
+            let mfa_enabled = user_repo
+                .get_mfa(&user_id)
+                .await?
+                .map_or(false, |mfa| mfa.enabled);
+
+            if mfa_enabled {
+                // The failures are only cleared once the second factor
+                // passed too.
+                let challenge = MfaChallenge {
+                    user_id,
+                    username: username.to_string(),
+                    role: user.role as u8,
+                    metadata,
+                };
+
+                return start_mfa_challenge(&*auth_repo, config, &challenge)
+                    .map(LoginResult::MfaRequired);
             }
 
-            let claims = Claims::new(user.id.unwrap().to_string(), 0, user.role as u8);
-
-            let token = create_token(claims, config.secret_key.as_str());
-
-            auth_repo
-                .create(user.id.unwrap(), token.as_str(), get_expired_seconds())
-                .map_err(|_| AppError::TokenNotExist)
+            record_success(&*auth_repo, username, ip.as_deref());
+            start_session(
+                &*auth_repo,
+                config,
+                key_store,
+                user_id,
+                user.role as u8,
+                metadata,
+            )
+            .map(LoginResult::Authenticated)
         }
     }
 }
 
-pub async fn logout_handler(claims: Claims, env: Environment) -> WebResult<impl Reply> {
-    logout(
+/// Replaces an outdated hash while the password is at hand, the login
+/// goes on when it fails.
+async fn upgrade_password(
+    user_repo: &impl UserRepository,
+    config: &Config,
+    user_id: &Uuid,
+    password: &str,
+) {
+    let upgraded = match hash_password(password, config) {
+        Some(hash) => user_repo.update_password(user_id, hash.as_str()).await,
+        None => Err(AppError::HashPasswordFailed),
+    };
+
+    if let Err(e) = upgraded {
+        warn!("password hash of {} isn't upgraded: {:?}", user_id, e);
+    }
+}
+
+pub async fn login_mfa_handler(
+    req: MfaLoginRequest,
+    ip: Option<IpAddr>,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    let subject = rate_limit_subject(RateLimitKey::Ip, None, ip);
+    env.rate_limiter
+        .check("login_mfa", subject.as_str(), &env.config.login_mfa_rate_limit)
+        .map_err(warp::reject::custom)?;
+
+    login_mfa(
+        env.user_repo,
         env.auth_repo,
-        uuid::Uuid::parse_str(claims.sub.as_str()).unwrap(),
+        &env.config,
+        &env.key_store,
+        req.mfa_token.as_str(),
+        req.code.as_str(),
     )
-    .map(|_| {
-        warp::reply::with_header(
-            "logout success",
-            "set-cookie",
-            create_cookie("deleted", chrono::Utc::now(), 0),
-        )
-    })
+    .await
+    .map(|tokens| token_response("login success", tokens, &env.config))
     .map_err(warp::reject::custom)
 }
 
-fn logout(auth_repo: Arc<impl AuthRepository>, id: Uuid) -> Result<(), AppError> {
-    auth_repo.expire(id).map_err(|_| AppError::TokenNotExist)
+/// The second step of a login with mfa, creates the session once the code
+/// of the mfa token is valid. Wrong codes count as failed logins, so a new
+/// mfa token doesn't bring new guesses.
+async fn login_mfa(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    key_store: &KeyStore,
+    mfa_token: &str,
+    code: &str,
+) -> Result<TokenPair, AppError> {
+    let challenge = auth_repo
+        .get_mfa_challenge(mfa_token)
+        .ok_or(AppError::AuthorizeFailed)?;
+    let username = challenge.username.as_str();
+    let ip = challenge.metadata.ip.clone();
+    check_lockout(&*auth_repo, config, username, ip.as_deref())?;
+
+    let mfa = user_repo
+        .get_mfa(&challenge.user_id)
+        .await?
+        .filter(|mfa| mfa.enabled)
+        .ok_or(AppError::AuthorizeFailed)?;
+
+    if let Err(e) = verify_second_factor(&*user_repo, &*auth_repo, &mfa, code).await {
+        let failures = auth_repo
+            .fail_mfa_challenge(mfa_token, get_mfa_challenge_expired_seconds(config))
+            .unwrap_or(MAX_MFA_FAILURES);
+        if failures >= MAX_MFA_FAILURES {
+            let _ = auth_repo.expire_mfa_challenge(mfa_token);
+        }
+        record_failure(&*auth_repo, config, username, ip.as_deref()).await;
+        return Err(e);
+    }
+
+    auth_repo
+        .expire_mfa_challenge(mfa_token)
+        .map_err(|_| AppError::TokenNotExist)?;
+    record_success(&*auth_repo, username, ip.as_deref());
+
+    start_session(
+        &*auth_repo,
+        config,
+        key_store,
+        challenge.user_id,
+        challenge.role,
+        challenge.metadata,
+    )
+}
+
+pub async fn logout_handler(principal: Principal, env: Environment) -> WebResult<impl Reply> {
+    logout(env.auth_repo, principal.claims().jti.as_str())
+        .map(|_| clear_cookies_response("logout success", &env.config.cookie))
+        .map_err(warp::reject::custom)
+}
+
+fn logout(auth_repo: Arc<impl AuthRepository>, session_id: &str) -> Result<(), AppError> {
+    auth_repo
+        .expire(session_id)
+        .map_err(|_| AppError::TokenNotExist)
 }
 
-pub async fn renew_handler(claims: Claims, env: Environment) -> WebResult<impl Reply> {
-    let seconds = 30 * 24 * 3600;
+pub async fn renew_handler(refresh_token: String, env: Environment) -> WebResult<impl Reply> {
     renew(
+        env.user_repo,
         env.auth_repo,
-        uuid::Uuid::parse_str(claims.sub.as_str()).unwrap(),
+        &env.config,
+        &env.key_store,
+        refresh_token.as_str(),
     )
-    .map(|_| {
-        warp::reply::with_header(
-            "renew success",
-            "set-cookie",
-            create_cookie(
-                create_token(claims, env.config.secret_key.as_str()).as_str(),
-                chrono::Utc::now() + chrono::Duration::days(30),
-                30 * 24 * 3600,
-            ),
-        )
-    })
+    .await
+    .map(|tokens| token_response("renew success", tokens, &env.config))
     .map_err(warp::reject::custom)
 }
 
-fn renew(auth_repo: Arc<impl AuthRepository>, id: Uuid) -> Result<(), AppError> {
+/// Rotates the refresh token, presenting a refresh token which was already
+/// rotated means it leaked, so the whole session is revoked.
+async fn renew(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    key_store: &KeyStore,
+    refresh_token: &str,
+) -> Result<TokenPair, AppError> {
+    let record = auth_repo
+        .get_refresh_token(refresh_token)
+        .ok_or(AppError::AuthorizeFailed)?;
+
+    let first_use = auth_repo
+        .consume_refresh_token(refresh_token, get_expired_seconds(config))
+        .map_err(|_| AppError::AuthorizeFailed)?;
+
+    if !first_use {
+        warn!(
+            "refresh token of the session {} is reused, revoke the session.",
+            record.session_id
+        );
+        let _ = auth_repo.expire(record.session_id.as_str());
+        return Err(AppError::AuthorizeFailed);
+    }
+
+    auth_repo
+        .renew(record.session_id.as_str(), get_expired_seconds(config))
+        .map_err(|_| AppError::TokenIsExpired)?;
+
+    let user = user_repo
+        .get(&record.user_id)
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+    check_status(user.status)?;
+
+    issue_tokens(
+        &*auth_repo,
+        config,
+        key_store,
+        user.id,
+        user.role as u8,
+        record.session_id.as_str(),
+    )
+}
+
+pub async fn list_sessions_handler(
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    list_sessions(env.auth_repo, principal.claims())
+        .map(|sessions| warp::reply::json(&sessions))
+        .map_err(warp::reject::custom)
+}
+
+fn list_sessions(
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+) -> Result<Vec<SessionResponse>, AppError> {
+    let user_id = Uuid::parse_str(claims.sub.as_str()).map_err(|_| AppError::AuthorizeFailed)?;
+
     auth_repo
-        .renew(id, get_expired_seconds())
+        .list(user_id)
+        .map(|sessions| {
+            sessions
+                .into_iter()
+                .map(|s| SessionResponse::new(s, claims.jti.as_str()))
+                .collect()
+        })
         .map_err(|_| AppError::TokenNotExist)
 }
 
-fn create_cookie(token: &str, expired_at: chrono::DateTime<Utc>, max_age: usize) -> String {
-    format!(
-        "token={}; path=/; httpOnly; expires={}; max-age={}",
+pub async fn revoke_session_handler(
+    session_id: String,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    revoke_session(env.auth_repo, principal.claims(), session_id.as_str())
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+fn revoke_session(
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    session_id: &str,
+) -> Result<(), AppError> {
+    let user_id = Uuid::parse_str(claims.sub.as_str()).map_err(|_| AppError::AuthorizeFailed)?;
+
+    // Only the sessions of the caller can be revoked.
+    match auth_repo.get(session_id) {
+        Some(session) if session.user_id == user_id => auth_repo
+            .expire(session_id)
+            .map_err(|_| AppError::TokenNotExist),
+        _ => Err(AppError::TokenNotExist),
+    }
+}
+
+pub async fn revoke_all_sessions_handler(
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    let user_id = Uuid::parse_str(principal.claims().sub.as_str())
+        .map_err(|_| warp::reject::custom(AppError::AuthorizeFailed))?;
+
+    env.auth_repo
+        .expire_all(user_id)
+        .map(|_| clear_cookies_response("all sessions revoked", &env.config.cookie))
+        .map_err(|_| warp::reject::custom(AppError::TokenNotExist))
+}
+
+pub async fn unlock_handler(
+    username: String,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    unlock(env.auth_repo, principal.claims(), username.as_str())
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+/// Lifts the lockout of a username before it expires.
+fn unlock(
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    username: &str,
+) -> Result<(), AppError> {
+    let subject = user_subject(username);
+
+    let locked = auth_repo
+        .unlock(subject.as_str())
+        .map_err(|_| AppError::DatabaseError)?;
+
+    if locked {
+        audit(
+            "login_unlocked",
+            subject.as_str(),
+            Some(claims.sub.as_str()),
+            None,
+        );
+    }
+
+    Ok(())
+}
+
+/// Public keys of the access tokens, so other services can verify them
+/// without calling the gateway.
+pub async fn jwks_handler(env: Environment) -> WebResult<impl Reply> {
+    Ok(warp::reply::json(env.key_store.jwks()))
+}
+
+fn token_response(
+    body: &'static str,
+    tokens: TokenPair,
+    config: &Config,
+) -> Response<&'static str> {
+    with_token_cookies(Response::builder(), tokens, config)
+        .body(body)
+        .unwrap()
+}
+
+/// Sets the token cookies, and a new csrf token the client echoes in the
+/// csrf header of state changing requests.
+pub(super) fn with_token_cookies(builder: Builder, tokens: TokenPair, config: &Config) -> Builder {
+    let cookie = &config.cookie;
+    let now = chrono::Utc::now();
+    let access_seconds = get_access_expired_seconds(config);
+    let refresh_seconds = get_expired_seconds(config);
+    let access_expired_at = now + chrono::Duration::seconds(access_seconds as i64);
+    let refresh_expired_at = now + chrono::Duration::seconds(refresh_seconds as i64);
+
+    builder
+        .header(
+            "set-cookie",
+            create_cookie(cookie, tokens.access_token.as_str(), access_expired_at, access_seconds),
+        )
+        .header(
+            "set-cookie",
+            create_refresh_cookie(
+                cookie,
+                tokens.refresh_token.as_str(),
+                refresh_expired_at,
+                refresh_seconds,
+            ),
+        )
+        .header(
+            "set-cookie",
+            cookie.build(
+                cookie.csrf_token_name(),
+                create_csrf_token().as_str(),
+                "/",
+                false,
+                refresh_expired_at,
+                refresh_seconds,
+            ),
+        )
+}
+
+fn clear_cookies_response(body: &'static str, cookie: &CookieConfig) -> Response<&'static str> {
+    let now = chrono::Utc::now();
+
+    Response::builder()
+        .header("set-cookie", create_cookie(cookie, "deleted", now, 0))
+        .header("set-cookie", create_refresh_cookie(cookie, "deleted", now, 0))
+        .header(
+            "set-cookie",
+            cookie.build(cookie.csrf_token_name(), "deleted", "/", false, now, 0),
+        )
+        .body(body)
+        .unwrap()
+}
+
+fn create_cookie(
+    cookie: &CookieConfig,
+    token: &str,
+    expired_at: chrono::DateTime<Utc>,
+    max_age: usize,
+) -> String {
+    cookie.build(cookie.token_name(), token, "/", true, expired_at, max_age)
+}
+
+// The refresh token is only sent to the renew endpoint.
+fn create_refresh_cookie(
+    cookie: &CookieConfig,
+    token: &str,
+    expired_at: chrono::DateTime<Utc>,
+    max_age: usize,
+) -> String {
+    cookie.build(
+        cookie.refresh_token_name(),
         token,
-        expired_at.to_rfc2822(),
-        max_age
+        "/api/v1/token",
+        true,
+        expired_at,
+        max_age,
     )
 }
 
 #[cfg(test)]
 mod test {
+    use jsonwebtoken::Algorithm;
+
     use crate::auth::handlers::create_token;
+    use crate::auth::json::session::Session;
+    use crate::auth::json::token::RefreshToken;
     use crate::auth::repo::MockAuthRepository;
-    use crate::core::util::hash_password;
+    use crate::user::json::mfa::Mfa;
     use crate::user::json::user::User;
     use crate::user::repo::MockUserRepository;
 
@@ -135,7 +520,8 @@ mod test {
     #[test]
     fn it_can_login() {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        let config = Config::new();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
 
         let mut user_mock_repo = MockUserRepository::new();
         let mut auth_mock_repo = MockAuthRepository::new();
@@ -146,8 +532,67 @@ mod test {
             Arc::new(user_mock_repo),
             Arc::new(auth_mock_repo),
             &config,
+            &key_store,
             "boris",
             "123",
+            SessionMetadata::default(),
+        ));
+        assert!(predict_token.is_ok());
+    }
+
+    #[test]
+    fn it_can_upgrade_outdated_hash_on_login() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo.expect_get_by_name().returning(|_| {
+            let config = Config::new().unwrap();
+            let password = argon2::hash_encoded(
+                b"123",
+                config.secret_key.as_bytes(),
+                &argon2::Config::default(),
+            )
+            .unwrap();
+            Ok(Some(User {
+                id: Some(uuid::Uuid::new_v4()),
+                name: "boris".to_string(),
+                password,
+                role: 0,
+                created_at: None,
+                updated_at: None,
+                status: 0,
+                deleted_at: None,
+                service_account: false,
+            }))
+        });
+        user_mock_repo.expect_get_mfa().returning(|_| Ok(None));
+        user_mock_repo
+            .expect_update_password()
+            .withf(|_, password| !needs_rehash(password, &Config::new().unwrap()))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        auth_mock_repo.expect_lockout_ttl().returning(|_| None);
+        auth_mock_repo
+            .expect_clear_login_failures()
+            .returning(|_| Ok(()));
+        auth_mock_repo.expect_create().returning(|_, _| Ok(()));
+        auth_mock_repo
+            .expect_create_refresh_token()
+            .returning(|_, _, _| Ok(()));
+
+        let predict_token = runtime.block_on(login(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "boris",
+            "123",
+            SessionMetadata::default(),
         ));
         assert!(predict_token.is_ok());
     }
@@ -155,7 +600,8 @@ mod test {
     #[test]
     fn it_cannot_login_because_password_is_wrong() {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        let config = Config::new();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
 
         let mut user_mock_repo = MockUserRepository::new();
         let mut auth_mock_repo = MockAuthRepository::new();
@@ -166,8 +612,10 @@ mod test {
             Arc::new(user_mock_repo),
             Arc::new(auth_mock_repo),
             &config,
+            &key_store,
             "boris",
             "123456",
+            SessionMetadata::default(),
         ));
         assert!(predict_token.is_err());
         assert_eq!(predict_token.unwrap_err(), AppError::AuthorizeFailed)
@@ -176,40 +624,116 @@ mod test {
     #[test]
     fn it_cannot_login_because_user_not_found() {
         let runtime = tokio::runtime::Runtime::new().unwrap();
-        let config = Config::new();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
 
         let mut user_mock_repo = MockUserRepository::new();
-        let auth_mock_repo = MockAuthRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
 
         user_mock_repo.expect_get_by_name().returning(|_| Ok(None));
+        auth_mock_repo.expect_lockout_ttl().returning(|_| None);
+        auth_mock_repo
+            .expect_record_login_failure()
+            .returning(|_, _| Ok(1));
 
         let predict_token = runtime.block_on(login(
             Arc::new(user_mock_repo),
             Arc::new(auth_mock_repo),
             &config,
+            &key_store,
             "boris",
             "123456",
+            SessionMetadata::default(),
         ));
         assert!(predict_token.is_err());
-        assert_eq!(predict_token.unwrap_err(), AppError::UserNotExist)
+        assert_eq!(predict_token.unwrap_err(), AppError::AuthorizeFailed)
+    }
+
+    #[test]
+    fn it_cannot_login_when_locked_out() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo
+            .expect_lockout_ttl()
+            .returning(|subject| (subject == "user: boris").then(|| 60));
+        user_mock_repo.expect_get_by_name().never();
+
+        let predict_token = runtime.block_on(login(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "Boris",
+            "123",
+            SessionMetadata::default(),
+        ));
+        assert_eq!(
+            predict_token.unwrap_err(),
+            AppError::LoginLocked { retry_after: 60 }
+        );
+    }
+
+    #[test]
+    fn it_cannot_login_when_disabled() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo.expect_get_by_name().returning(|_| {
+            let password = hash_password("123", &Config::new().unwrap()).unwrap();
+            Ok(Some(User {
+                id: Some(uuid::Uuid::new_v4()),
+                name: "boris".to_string(),
+                password,
+                role: 0,
+                created_at: None,
+                updated_at: None,
+                status: UserStatus::Disabled.into(),
+                deleted_at: None,
+                service_account: false,
+            }))
+        });
+        auth_mock_repo.expect_lockout_ttl().returning(|_| None);
+        auth_mock_repo.expect_create().never();
+
+        let predict_token = runtime.block_on(login(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "boris",
+            "123",
+            SessionMetadata::default(),
+        ));
+        assert_eq!(predict_token.unwrap_err(), AppError::UserDisabled);
     }
 
     #[test]
     fn it_can_create_token() {
-        let config = Config::new();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
         let claims = Claims::new(
             uuid::Uuid::new_v4().to_string(),
             chrono::Utc::now().timestamp() as usize,
             0,
+            uuid::Uuid::new_v4().to_string(),
         );
-        let token = create_token(claims, config.secret_key.as_str());
+        let token = create_token(claims, &key_store);
 
         assert!(!token.is_empty());
     }
 
     fn mock_returning(user_repo: &mut MockUserRepository, auth_repo: &mut MockAuthRepository) {
         user_repo.expect_get_by_name().returning(|_| {
-            let config = Config::new();
+            let config = Config::new().unwrap();
             let password = hash_password("123", &config).unwrap();
             Ok(Some(User {
                 id: Some(uuid::Uuid::new_v4()),
@@ -218,11 +742,181 @@ mod test {
                 role: 0,
                 created_at: None,
                 updated_at: None,
+                status: 0,
+                deleted_at: None,
+                service_account: false,
             }))
         });
 
+        user_repo.expect_get_mfa().returning(|_| Ok(None));
+
+        auth_repo.expect_lockout_ttl().returning(|_| None);
+        auth_repo
+            .expect_record_login_failure()
+            .returning(|_, _| Ok(1));
         auth_repo
-            .expect_create()
-            .returning(|id, token, seconds| Ok(token.to_string()));
+            .expect_clear_login_failures()
+            .returning(|_| Ok(()));
+        auth_repo.expect_create().returning(|_, _| Ok(()));
+        auth_repo
+            .expect_create_refresh_token()
+            .returning(|_, _, _| Ok(()));
+    }
+
+    #[test]
+    fn it_can_require_mfa_on_login() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo.expect_get_by_name().returning(|_| {
+            let password = hash_password("123", &Config::new().unwrap()).unwrap();
+            Ok(Some(User {
+                id: Some(uuid::Uuid::new_v4()),
+                name: "boris".to_string(),
+                password,
+                role: 2,
+                created_at: None,
+                updated_at: None,
+                status: 0,
+                deleted_at: None,
+                service_account: false,
+            }))
+        });
+        user_mock_repo.expect_get_mfa().returning(|user_id| {
+            Ok(Some(Mfa {
+                user_id: *user_id,
+                secret: vec![0; 20],
+                enabled: true,
+            }))
+        });
+        auth_mock_repo.expect_lockout_ttl().returning(|_| None);
+        auth_mock_repo.expect_clear_login_failures().never();
+        auth_mock_repo
+            .expect_create_mfa_challenge()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        auth_mock_repo.expect_create().never();
+
+        let result = runtime.block_on(login(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "boris",
+            "123",
+            SessionMetadata::default(),
+        ));
+
+        assert!(matches!(result, Ok(LoginResult::MfaRequired(_))));
+    }
+
+    #[test]
+    fn it_can_count_wrong_mfa_codes_as_login_failures() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo.expect_get_mfa_challenge().returning(|_| {
+            Some(MfaChallenge {
+                user_id: uuid::Uuid::new_v4(),
+                username: "boris".to_string(),
+                role: 0,
+                metadata: SessionMetadata::default(),
+            })
+        });
+        user_mock_repo.expect_get_mfa().returning(|user_id| {
+            Ok(Some(Mfa {
+                user_id: *user_id,
+                secret: vec![0; 20],
+                enabled: true,
+            }))
+        });
+        user_mock_repo
+            .expect_consume_recovery_code()
+            .returning(|_, _| Ok(false));
+        auth_mock_repo.expect_lockout_ttl().returning(|_| None);
+        auth_mock_repo
+            .expect_fail_mfa_challenge()
+            .returning(|_, _| Ok(1));
+        auth_mock_repo
+            .expect_record_login_failure()
+            .withf(|subject, _| subject == user_subject("boris"))
+            .times(1)
+            .returning(|_, _| Ok(1));
+        auth_mock_repo.expect_clear_login_failures().never();
+        auth_mock_repo.expect_create().never();
+
+        let result = runtime.block_on(login_mfa(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "mfa_token",
+            "not a code",
+        ));
+
+        assert_eq!(result.unwrap_err(), AppError::InvalidMfaCode);
+    }
+
+    #[test]
+    fn it_can_revoke_session_when_refresh_token_is_reused() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+
+        let user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo.expect_get_refresh_token().returning(|_| {
+            Some(RefreshToken {
+                session_id: "session".to_string(),
+                user_id: uuid::Uuid::new_v4(),
+            })
+        });
+        auth_mock_repo
+            .expect_consume_refresh_token()
+            .returning(|_, _| Ok(false));
+        auth_mock_repo
+            .expect_expire()
+            .withf(|session_id| session_id == "session")
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let result = runtime.block_on(renew(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &key_store,
+            "refresh_token",
+        ));
+
+        assert_eq!(result.unwrap_err(), AppError::AuthorizeFailed);
+    }
+
+    #[test]
+    fn it_cannot_revoke_session_of_other_user() {
+        let mut auth_mock_repo = MockAuthRepository::new();
+        let other_user_id = uuid::Uuid::new_v4();
+
+        auth_mock_repo
+            .expect_get()
+            .returning(move |_| Some(Session::new(other_user_id, SessionMetadata::default())));
+
+        let claims = Claims::new(
+            uuid::Uuid::new_v4().to_string(),
+            0,
+            0,
+            uuid::Uuid::new_v4().to_string(),
+        );
+
+        let result = revoke_session(Arc::new(auth_mock_repo), &claims, "session");
+        assert_eq!(result.unwrap_err(), AppError::TokenNotExist);
     }
 }
@@ -1,14 +1,37 @@
 use serde::{Deserialize, Serialize};
 
+use crate::auth::role::Permission;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    pub iat: usize,
+    pub nbf: usize,
     pub exp: usize,
+    pub iss: String,
+    pub aud: String,
     pub role: u8,
+    pub jti: String,
+    /// Set for api keys, which only grant these permissions of the role.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Permission>>,
 }
 
 impl Claims {
-    pub fn new(sub: String, exp: usize, role: u8) -> Self {
-        Self { sub, exp, role }
+    /// Claims issued now, the issuer and the audience are left empty.
+    pub fn new(sub: String, exp: usize, role: u8, jti: String) -> Self {
+        let now = chrono::Utc::now().timestamp() as usize;
+
+        Self {
+            sub,
+            iat: now,
+            nbf: now,
+            exp,
+            iss: String::new(),
+            aud: String::new(),
+            role,
+            jti,
+            scopes: None,
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::json::session::SessionMetadata;
+
+/// A login which passed the password step and waits for the second factor,
+/// stored in redis under the mfa token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub user_id: Uuid,
+    /// The login name, wrong codes count against its lockout.
+    pub username: String,
+    pub role: u8,
+    pub metadata: SessionMetadata,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub expires_in: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    pub code: String,
+}
@@ -1,2 +1,8 @@
 pub mod claims;
+pub mod jwks;
+pub mod mfa;
+pub mod oidc;
+pub mod principal;
 pub mod request;
+pub mod session;
+pub mod token;
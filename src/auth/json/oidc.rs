@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::auth::role::Role;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    #[serde(default)]
+    pub providers: Vec<OidcProviderConfig>,
+    /// Where the browser is sent once the gateway session is created.
+    #[serde(default = "default_post_login_redirect")]
+    pub post_login_redirect: String,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            providers: vec![],
+            post_login_redirect: default_post_login_redirect(),
+        }
+    }
+}
+
+impl OidcConfig {
+    pub fn from_path(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config = toml::from_str::<OidcConfig>(content.as_str())?;
+        Ok(config)
+    }
+}
+
+/// An external OpenID Connect identity provider, its endpoints are read
+/// from `{issuer}/.well-known/openid-configuration`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// Role of the users created on their first login.
+    #[serde(default = "default_role")]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub id_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+/// What the callback needs to finish the flow started by the login
+/// redirect, kept in redis under the `state` parameter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+fn default_post_login_redirect() -> String {
+    "/".to_string()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+fn default_role() -> Role {
+    Role::User
+}
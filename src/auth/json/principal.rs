@@ -0,0 +1,30 @@
+use crate::auth::json::claims::Claims;
+
+/// Who a request is made by, and how it authenticated.
+#[derive(Debug)]
+pub enum Principal {
+    /// A user with a session token, sent in the header or the cookie.
+    User(Claims),
+    /// An api key of a user, limited to the scopes of the key.
+    ApiKey(Claims),
+    /// An api key of a service account.
+    Service(Claims),
+}
+
+impl Principal {
+    pub fn claims(&self) -> &Claims {
+        match self {
+            Principal::User(claims) | Principal::ApiKey(claims) | Principal::Service(claims) => {
+                claims
+            }
+        }
+    }
+
+    pub fn into_claims(self) -> Claims {
+        match self {
+            Principal::User(claims) | Principal::ApiKey(claims) | Principal::Service(claims) => {
+                claims
+            }
+        }
+    }
+}
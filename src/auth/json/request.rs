@@ -4,4 +4,5 @@ use serde::Deserialize;
 pub struct AuthRequest {
     pub username: String,
     pub password: String,
+    pub device: Option<String>,
 }
@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Where a session was created from, shown when a user lists the sessions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// A login of a user, the id is the `jti` claim of the token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: Uuid,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub fn new(user_id: Uuid, metadata: SessionMetadata) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            device: metadata.device,
+            ip: metadata.ip,
+            user_agent: metadata.user_agent,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: String,
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(session: Session, current_id: &str) -> Self {
+        Self {
+            current: session.id == current_id,
+            id: session.id,
+            device: session.device,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            created_at: session.created_at,
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What an opaque refresh token stands for, stored in redis under the hash
+/// of the token. Every refresh token of a session belongs to the same family.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub session_id: String,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// A password login either creates the session, or waits for the second
+/// factor of the mfa token.
+#[derive(Debug, Clone)]
+pub enum LoginResult {
+    Authenticated(TokenPair),
+    MfaRequired(String),
+}
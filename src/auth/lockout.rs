@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::auth::repo::AuthRepository;
+use crate::core::audit::audit;
+use crate::core::config::Config;
+use crate::core::error::AppError;
+
+// Failures before the responses start to slow down, then the delay doubles
+// with every failure.
+const FREE_FAILURES: u32 = 2;
+const BASE_DELAY_MS: u64 = 250;
+const MAX_DELAY_MS: u64 = 4000;
+
+/// Failures are counted per username, whether the user exists or not, so
+/// a lockout doesn't tell which usernames exist.
+pub fn user_subject(username: &str) -> String {
+    format!("user: {}", username.trim().to_lowercase())
+}
+
+pub fn ip_subject(ip: &str) -> String {
+    format!("ip: {}", ip)
+}
+
+fn subjects(username: &str, ip: Option<&str>, config: &Config) -> Vec<(String, u32)> {
+    let mut subjects = vec![(user_subject(username), config.login_max_user_failures)];
+    if let Some(ip) = ip {
+        subjects.push((ip_subject(ip), config.login_max_ip_failures));
+    }
+    subjects
+}
+
+/// Rejects the login while the username or the ip is locked out.
+pub fn check_lockout(
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<(), AppError> {
+    let retry_after = subjects(username, ip, config)
+        .iter()
+        .filter_map(|(subject, _)| auth_repo.lockout_ttl(subject.as_str()))
+        .max();
+
+    match retry_after {
+        Some(retry_after) => Err(AppError::LoginLocked { retry_after }),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed login, locks the subjects which reached their limit and
+/// slows the response down.
+pub async fn record_failure(
+    auth_repo: &impl AuthRepository,
+    config: &Config,
+    username: &str,
+    ip: Option<&str>,
+) {
+    let mut delay = Duration::ZERO;
+
+    for (subject, max_failures) in subjects(username, ip, config) {
+        let failures = match auth_repo
+            .record_login_failure(subject.as_str(), config.login_lockout_seconds)
+        {
+            Ok(failures) => failures,
+            Err(e) => {
+                warn!("failed login of {} isn't counted: {}", subject, e);
+                continue;
+            }
+        };
+
+        if failures >= max_failures {
+            match auth_repo.lock(subject.as_str(), config.login_lockout_seconds) {
+                Ok(_) => audit("login_locked", subject.as_str(), None, ip),
+                Err(e) => warn!("{} can't be locked: {}", subject, e),
+            }
+        }
+
+        delay = delay.max(failure_delay(failures));
+    }
+
+    audit("login_failed", user_subject(username).as_str(), None, ip);
+    tokio::time::sleep(delay).await;
+}
+
+/// Resets the failures of the username, the failures of the ip are kept so
+/// an attacker can't reset them with an account of its own.
+pub fn record_success(auth_repo: &impl AuthRepository, username: &str, ip: Option<&str>) {
+    let subject = user_subject(username);
+
+    if let Err(e) = auth_repo.clear_login_failures(subject.as_str()) {
+        warn!("failures of {} can't be cleared: {}", subject, e);
+    }
+    audit("login_succeeded", subject.as_str(), None, ip);
+}
+
+pub fn failure_delay(failures: u32) -> Duration {
+    if failures <= FREE_FAILURES {
+        return Duration::ZERO;
+    }
+
+    let exponent = (failures - FREE_FAILURES - 1).min(16);
+    Duration::from_millis((BASE_DELAY_MS << exponent).min(MAX_DELAY_MS))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::middlewares::rate_limit::resolve_client_ip;
+
+    use super::*;
+
+    #[test]
+    fn it_can_slow_down_failures_progressively() {
+        assert_eq!(failure_delay(1), Duration::ZERO);
+        assert_eq!(failure_delay(2), Duration::ZERO);
+        assert_eq!(failure_delay(3), Duration::from_millis(250));
+        assert_eq!(failure_delay(4), Duration::from_millis(500));
+        assert_eq!(failure_delay(100), Duration::from_millis(MAX_DELAY_MS));
+    }
+
+    #[test]
+    fn it_can_count_usernames_case_insensitively() {
+        assert_eq!(user_subject(" Boris"), user_subject("boris"));
+    }
+
+    #[test]
+    fn it_cannot_change_ip_subject_with_forged_forwarded_for() {
+        let remote = Some("203.0.113.7".parse().unwrap());
+        let subject = |forwarded: Option<&str>| {
+            let ip = resolve_client_ip(forwarded, remote, &[]).unwrap();
+            ip_subject(ip.to_string().as_str())
+        };
+
+        assert_eq!(subject(Some("198.51.100.1")), subject(None));
+        assert_eq!(subject(Some("198.51.100.1, 198.51.100.2")), "ip: 203.0.113.7");
+    }
+}
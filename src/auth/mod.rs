@@ -1,4 +1,8 @@
 pub mod handlers;
 pub mod json;
+pub mod keys;
+pub mod lockout;
+pub mod oidc;
 pub mod repo;
+pub mod role;
 pub mod route;
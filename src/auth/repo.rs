@@ -1,17 +1,96 @@
 use async_trait::async_trait;
 use r2d2_redis::{r2d2, redis, RedisConnectionManager};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use crate::api_key::json::api_key::CachedApiKey;
+use crate::auth::json::mfa::MfaChallenge;
+use crate::auth::json::oidc::OidcState;
+use crate::auth::json::session::Session;
+use crate::auth::json::token::RefreshToken;
 use crate::AppResult;
 
 pub trait AuthRepository {
-    fn create(&self, id: Uuid, token: &str, seconds: usize) -> AppResult<String>;
+    fn create(&self, session: &Session, seconds: usize) -> AppResult<()>;
 
-    fn expire(&self, id: Uuid) -> AppResult<()>;
+    fn expire(&self, session_id: &str) -> AppResult<()>;
 
-    fn renew(&self, id: Uuid, seconds: usize) -> AppResult<()>;
+    fn expire_all(&self, user_id: Uuid) -> AppResult<()>;
 
-    fn get(&self, id: Uuid) -> Option<String>;
+    fn renew(&self, session_id: &str, seconds: usize) -> AppResult<()>;
+
+    fn get(&self, session_id: &str) -> Option<Session>;
+
+    fn list(&self, user_id: Uuid) -> AppResult<Vec<Session>>;
+
+    fn create_refresh_token(
+        &self,
+        token: &str,
+        refresh_token: &RefreshToken,
+        seconds: usize,
+    ) -> AppResult<()>;
+
+    fn get_refresh_token(&self, token: &str) -> Option<RefreshToken>;
+
+    /// Marks the refresh token as used, returns `false` when it was used before.
+    fn consume_refresh_token(&self, token: &str, seconds: usize) -> AppResult<bool>;
+
+    fn create_oidc_state(&self, state: &str, value: &OidcState, seconds: usize) -> AppResult<()>;
+
+    /// Gets and deletes the state, so a callback can't be replayed.
+    fn take_oidc_state(&self, state: &str) -> Option<OidcState>;
+
+    fn create_mfa_challenge(
+        &self,
+        token: &str,
+        challenge: &MfaChallenge,
+        seconds: usize,
+    ) -> AppResult<()>;
+
+    fn get_mfa_challenge(&self, token: &str) -> Option<MfaChallenge>;
+
+    /// Counts a wrong code of the challenge, returns the count so far.
+    fn fail_mfa_challenge(&self, token: &str, seconds: usize) -> AppResult<u32>;
+
+    fn expire_mfa_challenge(&self, token: &str) -> AppResult<()>;
+
+    /// Marks the totp step of the user as used, returns `false` when the
+    /// code of the step was used before.
+    fn consume_totp_step(&self, user_id: Uuid, step: u64, seconds: usize) -> AppResult<bool>;
+
+    /// Seconds left of the lockout of the subject, if it's locked out.
+    fn lockout_ttl(&self, subject: &str) -> Option<u64>;
+
+    /// Counts a failed login of the subject, returns the count within the
+    /// last `seconds`.
+    fn record_login_failure(&self, subject: &str, seconds: usize) -> AppResult<u32>;
+
+    fn clear_login_failures(&self, subject: &str) -> AppResult<()>;
+
+    fn lock(&self, subject: &str, seconds: usize) -> AppResult<()>;
+
+    /// Lifts the lockout and its failures, returns `false` when the subject
+    /// wasn't locked out.
+    fn unlock(&self, subject: &str) -> AppResult<bool>;
+
+    fn create_password_reset(&self, token: &str, user_id: Uuid, seconds: usize) -> AppResult<()>;
+
+    /// Gets and deletes the reset token, so it can only be used once.
+    fn take_password_reset(&self, token: &str) -> Option<Uuid>;
+
+    /// Revokes every session of the user, and refuses its access tokens
+    /// until it's unblocked.
+    fn block_user(&self, user_id: Uuid) -> AppResult<()>;
+
+    fn unblock_user(&self, user_id: Uuid) -> AppResult<()>;
+
+    fn is_user_blocked(&self, user_id: Uuid) -> bool;
+
+    fn cache_api_key(&self, cached: &CachedApiKey, seconds: usize) -> AppResult<()>;
+
+    fn get_cached_api_key(&self, id: &Uuid) -> Option<CachedApiKey>;
+
+    fn evict_api_key(&self, id: &Uuid) -> AppResult<()>;
 }
 
 #[derive(Clone)]
@@ -26,8 +105,81 @@ impl RedisAuthRepository {
         }
     }
 
-    fn user_id_to_key(&self, id: &Uuid) -> String {
-        format!("user_id: {}", id)
+    fn session_id_to_key(&self, id: &str) -> String {
+        format!("session: {}", id)
+    }
+
+    fn user_id_to_sessions_key(&self, id: &Uuid) -> String {
+        format!("user_sessions: {}", id)
+    }
+
+    // Only the hash of a refresh token is kept, so a dump of redis can't be
+    // used to renew sessions.
+    fn refresh_token_to_key(&self, token: &str) -> String {
+        format!("refresh_token: {:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    fn refresh_token_to_used_key(&self, token: &str) -> String {
+        format!("refresh_token_used: {:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    fn session_id_to_family_key(&self, id: &str) -> String {
+        format!("refresh_family: {}", id)
+    }
+
+    fn oidc_state_to_key(&self, state: &str) -> String {
+        format!("oidc_state: {}", state)
+    }
+
+    fn mfa_token_to_key(&self, token: &str) -> String {
+        format!("mfa_challenge: {:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    fn mfa_token_to_failures_key(&self, token: &str) -> String {
+        format!("mfa_challenge_failures: {:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    fn subject_to_failures_key(&self, subject: &str) -> String {
+        format!("login_failures: {}", subject)
+    }
+
+    fn subject_to_lockout_key(&self, subject: &str) -> String {
+        format!("login_lockout: {}", subject)
+    }
+
+    fn user_id_to_blocked_key(&self, id: &Uuid) -> String {
+        format!("blocked_user: {}", id)
+    }
+
+    fn api_key_id_to_key(&self, id: &Uuid) -> String {
+        format!("api_key: {}", id)
+    }
+
+    fn password_reset_to_key(&self, token: &str) -> String {
+        format!("password_reset: {:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    /// Adds the commands deleting a session and all its refresh tokens.
+    fn expire_session(
+        &self,
+        pipe: &mut redis::Pipeline,
+        conn: &mut redis::Connection,
+        session_id: &str,
+    ) -> AppResult<()> {
+        let family_key = self.session_id_to_family_key(session_id);
+        let keys = redis::cmd("SMEMBERS")
+            .arg(family_key.as_str())
+            .query::<Vec<String>>(conn)?;
+
+        for key in keys {
+            pipe.cmd("DEL").arg(key).ignore();
+        }
+        pipe.cmd("DEL").arg(family_key).ignore();
+        pipe.cmd("DEL")
+            .arg(self.session_id_to_key(session_id))
+            .ignore();
+
+        Ok(())
     }
 
     fn get_connection(&self) -> r2d2::PooledConnection<RedisConnectionManager> {
@@ -37,37 +189,397 @@ impl RedisAuthRepository {
 
 #[async_trait]
 impl AuthRepository for RedisAuthRepository {
-    fn create(&self, id: Uuid, token: &str, seconds: usize) -> AppResult<String> {
+    fn create(&self, session: &Session, seconds: usize) -> AppResult<()> {
+        let value = serde_json::to_string(session)?;
+        let sessions_key = self.user_id_to_sessions_key(&session.user_id);
+
+        redis::pipe()
+            .atomic()
+            .cmd("SETEX")
+            .arg(self.session_id_to_key(session.id.as_str()))
+            .arg(seconds)
+            .arg(value)
+            .ignore()
+            .cmd("SADD")
+            .arg(sessions_key.as_str())
+            .arg(session.id.as_str())
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(sessions_key.as_str())
+            .arg(seconds)
+            .ignore()
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn expire(&self, session_id: &str) -> AppResult<()> {
+        let session = self.get(session_id);
+        let mut conn = self.get_connection();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.expire_session(&mut pipe, &mut *conn, session_id)?;
+
+        if let Some(session) = session {
+            pipe.cmd("SREM")
+                .arg(self.user_id_to_sessions_key(&session.user_id))
+                .arg(session_id)
+                .ignore();
+        }
+
+        pipe.query::<()>(&mut *conn)?;
+
+        Ok(())
+    }
+
+    fn expire_all(&self, user_id: Uuid) -> AppResult<()> {
+        let sessions_key = self.user_id_to_sessions_key(&user_id);
+        let mut conn = self.get_connection();
+
+        let ids = redis::cmd("SMEMBERS")
+            .arg(sessions_key.as_str())
+            .query::<Vec<String>>(&mut *conn)?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for id in ids.iter() {
+            self.expire_session(&mut pipe, &mut *conn, id)?;
+        }
+        pipe.cmd("DEL").arg(sessions_key.as_str()).ignore();
+        pipe.query::<()>(&mut *conn)?;
+
+        Ok(())
+    }
+
+    fn renew(&self, session_id: &str, seconds: usize) -> AppResult<()> {
+        let session = self
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("session {} doesn't exist.", session_id))?;
+
+        redis::pipe()
+            .cmd("EXPIRE")
+            .arg(self.session_id_to_key(session_id))
+            .arg(seconds)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(self.user_id_to_sessions_key(&session.user_id))
+            .arg(seconds)
+            .ignore()
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn get(&self, session_id: &str) -> Option<Session> {
+        redis::cmd("GET")
+            .arg(self.session_id_to_key(session_id))
+            .query::<String>(&mut *self.get_connection())
+            .ok()
+            .and_then(|value| serde_json::from_str::<Session>(value.as_str()).ok())
+    }
+
+    fn list(&self, user_id: Uuid) -> AppResult<Vec<Session>> {
+        let sessions_key = self.user_id_to_sessions_key(&user_id);
+        let mut conn = self.get_connection();
+
+        let ids = redis::cmd("SMEMBERS")
+            .arg(sessions_key.as_str())
+            .query::<Vec<String>>(&mut *conn)?;
+
+        let mut sessions = vec![];
+        for id in ids {
+            let value = redis::cmd("GET")
+                .arg(self.session_id_to_key(id.as_str()))
+                .query::<Option<String>>(&mut *conn)?;
+
+            match value.and_then(|v| serde_json::from_str::<Session>(v.as_str()).ok()) {
+                Some(session) => sessions.push(session),
+                // The session is expired, drop it from the index as well.
+                None => redis::cmd("SREM")
+                    .arg(sessions_key.as_str())
+                    .arg(id)
+                    .query::<()>(&mut *conn)?,
+            }
+        }
+
+        sessions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(sessions)
+    }
+
+    fn create_refresh_token(
+        &self,
+        token: &str,
+        refresh_token: &RefreshToken,
+        seconds: usize,
+    ) -> AppResult<()> {
+        let value = serde_json::to_string(refresh_token)?;
+        let key = self.refresh_token_to_key(token);
+        let family_key = self.session_id_to_family_key(refresh_token.session_id.as_str());
+
+        redis::pipe()
+            .atomic()
+            .cmd("SETEX")
+            .arg(key.as_str())
+            .arg(seconds)
+            .arg(value)
+            .ignore()
+            .cmd("SADD")
+            .arg(family_key.as_str())
+            .arg(key.as_str())
+            .arg(self.refresh_token_to_used_key(token))
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(family_key.as_str())
+            .arg(seconds)
+            .ignore()
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn get_refresh_token(&self, token: &str) -> Option<RefreshToken> {
+        redis::cmd("GET")
+            .arg(self.refresh_token_to_key(token))
+            .query::<String>(&mut *self.get_connection())
+            .ok()
+            .and_then(|value| serde_json::from_str::<RefreshToken>(value.as_str()).ok())
+    }
+
+    fn consume_refresh_token(&self, token: &str, seconds: usize) -> AppResult<bool> {
+        let result = redis::cmd("SET")
+            .arg(self.refresh_token_to_used_key(token))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(seconds)
+            .query::<Option<String>>(&mut *self.get_connection())?;
+
+        Ok(result.is_some())
+    }
+
+    fn create_oidc_state(&self, state: &str, value: &OidcState, seconds: usize) -> AppResult<()> {
+        let value = serde_json::to_string(value)?;
+
+        redis::cmd("SETEX")
+            .arg(self.oidc_state_to_key(state))
+            .arg(seconds)
+            .arg(value)
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn take_oidc_state(&self, state: &str) -> Option<OidcState> {
+        let key = self.oidc_state_to_key(state);
+
+        redis::pipe()
+            .atomic()
+            .cmd("GET")
+            .arg(key.as_str())
+            .cmd("DEL")
+            .arg(key.as_str())
+            .ignore()
+            .query::<(Option<String>,)>(&mut *self.get_connection())
+            .ok()
+            .and_then(|(value,)| value)
+            .and_then(|value| serde_json::from_str::<OidcState>(value.as_str()).ok())
+    }
+
+    fn create_mfa_challenge(
+        &self,
+        token: &str,
+        challenge: &MfaChallenge,
+        seconds: usize,
+    ) -> AppResult<()> {
+        let value = serde_json::to_string(challenge)?;
+
+        redis::cmd("SETEX")
+            .arg(self.mfa_token_to_key(token))
+            .arg(seconds)
+            .arg(value)
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn get_mfa_challenge(&self, token: &str) -> Option<MfaChallenge> {
+        redis::cmd("GET")
+            .arg(self.mfa_token_to_key(token))
+            .query::<String>(&mut *self.get_connection())
+            .ok()
+            .and_then(|value| serde_json::from_str::<MfaChallenge>(value.as_str()).ok())
+    }
+
+    fn fail_mfa_challenge(&self, token: &str, seconds: usize) -> AppResult<u32> {
+        let key = self.mfa_token_to_failures_key(token);
+
+        let (failures,) = redis::pipe()
+            .atomic()
+            .cmd("INCR")
+            .arg(key.as_str())
+            .cmd("EXPIRE")
+            .arg(key.as_str())
+            .arg(seconds)
+            .ignore()
+            .query::<(u32,)>(&mut *self.get_connection())?;
+
+        Ok(failures)
+    }
+
+    fn expire_mfa_challenge(&self, token: &str) -> AppResult<()> {
+        redis::cmd("DEL")
+            .arg(self.mfa_token_to_key(token))
+            .arg(self.mfa_token_to_failures_key(token))
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn consume_totp_step(&self, user_id: Uuid, step: u64, seconds: usize) -> AppResult<bool> {
+        let result = redis::cmd("SET")
+            .arg(format!("totp_used: {}: {}", user_id, step))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(seconds)
+            .query::<Option<String>>(&mut *self.get_connection())?;
+
+        Ok(result.is_some())
+    }
+
+    fn lockout_ttl(&self, subject: &str) -> Option<u64> {
+        redis::cmd("TTL")
+            .arg(self.subject_to_lockout_key(subject))
+            .query::<i64>(&mut *self.get_connection())
+            .ok()
+            .filter(|ttl| *ttl > 0)
+            .map(|ttl| ttl as u64)
+    }
+
+    fn record_login_failure(&self, subject: &str, seconds: usize) -> AppResult<u32> {
+        let key = self.subject_to_failures_key(subject);
+
+        let (failures,) = redis::pipe()
+            .atomic()
+            .cmd("INCR")
+            .arg(key.as_str())
+            .cmd("EXPIRE")
+            .arg(key.as_str())
+            .arg(seconds)
+            .ignore()
+            .query::<(u32,)>(&mut *self.get_connection())?;
+
+        Ok(failures)
+    }
+
+    fn clear_login_failures(&self, subject: &str) -> AppResult<()> {
+        redis::cmd("DEL")
+            .arg(self.subject_to_failures_key(subject))
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn lock(&self, subject: &str, seconds: usize) -> AppResult<()> {
         redis::cmd("SETEX")
-            .arg(self.user_id_to_key(&id))
+            .arg(self.subject_to_lockout_key(subject))
             .arg(seconds)
-            .arg(token)
-            .execute(&mut *self.get_connection());
+            .arg(1)
+            .query::<()>(&mut *self.get_connection())?;
 
-        Ok(token.to_owned())
+        Ok(())
     }
 
-    fn expire(&self, id: Uuid) -> AppResult<()> {
+    fn unlock(&self, subject: &str) -> AppResult<bool> {
+        let (locks, _) = redis::pipe()
+            .atomic()
+            .cmd("DEL")
+            .arg(self.subject_to_lockout_key(subject))
+            .cmd("DEL")
+            .arg(self.subject_to_failures_key(subject))
+            .query::<(u32, u32)>(&mut *self.get_connection())?;
+
+        Ok(locks > 0)
+    }
+
+    fn create_password_reset(&self, token: &str, user_id: Uuid, seconds: usize) -> AppResult<()> {
+        redis::cmd("SETEX")
+            .arg(self.password_reset_to_key(token))
+            .arg(seconds)
+            .arg(user_id.to_string())
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
+    }
+
+    fn take_password_reset(&self, token: &str) -> Option<Uuid> {
+        let key = self.password_reset_to_key(token);
+
+        redis::pipe()
+            .atomic()
+            .cmd("GET")
+            .arg(key.as_str())
+            .cmd("DEL")
+            .arg(key.as_str())
+            .ignore()
+            .query::<(Option<String>,)>(&mut *self.get_connection())
+            .ok()
+            .and_then(|(value,)| value)
+            .and_then(|value| Uuid::parse_str(value.as_str()).ok())
+    }
+
+    fn block_user(&self, user_id: Uuid) -> AppResult<()> {
+        // Blocked first, a session created while the others are revoked
+        // can't be used either.
+        redis::cmd("SET")
+            .arg(self.user_id_to_blocked_key(&user_id))
+            .arg(1)
+            .query::<()>(&mut *self.get_connection())?;
+
+        self.expire_all(user_id)
+    }
+
+    fn unblock_user(&self, user_id: Uuid) -> AppResult<()> {
         redis::cmd("DEL")
-            .arg(self.user_id_to_key(&id))
-            .execute(&mut *self.get_connection());
+            .arg(self.user_id_to_blocked_key(&user_id))
+            .query::<()>(&mut *self.get_connection())?;
 
         Ok(())
     }
 
-    fn renew(&self, id: Uuid, seconds: usize) -> AppResult<()> {
-        redis::cmd("Expire")
-            .arg(self.user_id_to_key(&id))
+    fn is_user_blocked(&self, user_id: Uuid) -> bool {
+        redis::cmd("EXISTS")
+            .arg(self.user_id_to_blocked_key(&user_id))
+            .query::<bool>(&mut *self.get_connection())
+            .unwrap_or(true)
+    }
+
+    fn cache_api_key(&self, cached: &CachedApiKey, seconds: usize) -> AppResult<()> {
+        let value = serde_json::to_string(cached)?;
+
+        redis::cmd("SETEX")
+            .arg(self.api_key_id_to_key(&cached.api_key.id))
             .arg(seconds)
-            .execute(&mut *self.get_connection());
+            .arg(value)
+            .query::<()>(&mut *self.get_connection())?;
 
         Ok(())
     }
 
-    fn get(&self, id: Uuid) -> Option<String> {
+    fn get_cached_api_key(&self, id: &Uuid) -> Option<CachedApiKey> {
         redis::cmd("GET")
-            .arg(self.user_id_to_key(&id))
+            .arg(self.api_key_id_to_key(id))
             .query::<String>(&mut *self.get_connection())
             .ok()
+            .and_then(|value| serde_json::from_str::<CachedApiKey>(value.as_str()).ok())
+    }
+
+    fn evict_api_key(&self, id: &Uuid) -> AppResult<()> {
+        redis::cmd("DEL")
+            .arg(self.api_key_id_to_key(id))
+            .query::<()>(&mut *self.get_connection())?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,88 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::error::AppError;
+
+/// Named roles of the numeric `users.role` column and `Claims.role`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User = 0,
+    Staff = 1,
+    Admin = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ReadUsers,
+    ManageUsers,
+    ManageUpstreams,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Role::User => &[],
+            Role::Staff => &[Permission::ReadUsers],
+            Role::Admin => &[
+                Permission::ReadUsers,
+                Permission::ManageUsers,
+                Permission::ManageUpstreams,
+            ],
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl TryFrom<i16> for Role {
+    type Error = AppError;
+
+    fn try_from(value: i16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Role::User),
+            1 => Ok(Role::Staff),
+            2 => Ok(Role::Admin),
+            _ => Err(AppError::Forbidden),
+        }
+    }
+}
+
+impl TryFrom<u8> for Role {
+    type Error = AppError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Role::try_from(value as i16)
+    }
+}
+
+impl From<Role> for i16 {
+    fn from(role: Role) -> Self {
+        role as i16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_convert_numeric_role() {
+        assert_eq!(Role::try_from(0u8), Ok(Role::User));
+        assert_eq!(Role::try_from(2i16), Ok(Role::Admin));
+        assert_eq!(Role::try_from(9u8), Err(AppError::Forbidden));
+        assert_eq!(i16::from(Role::Staff), 1);
+    }
+
+    #[test]
+    fn it_can_check_permissions_of_role() {
+        assert!(Role::Admin.has_permission(Permission::ManageUpstreams));
+        assert!(Role::Staff.has_permission(Permission::ReadUsers));
+        assert!(!Role::Staff.has_permission(Permission::ManageUsers));
+        assert!(!Role::User.has_permission(Permission::ReadUsers));
+    }
+}
@@ -1,8 +1,15 @@
 use warp::{Filter, Reply};
 use warp::filters::BoxedFilter;
 
-use crate::auth::handlers::v1::{login_handler, logout_handler, renew_handler};
-use crate::core::middlewares::authorization::{authenticated_from_cookie};
+use crate::auth::handlers::oidc::{oidc_callback_handler, oidc_login_handler};
+use crate::auth::handlers::v1::{
+    jwks_handler, list_sessions_handler, login_handler, login_mfa_handler, logout_handler,
+    renew_handler, revoke_all_sessions_handler, revoke_session_handler, unlock_handler,
+};
+use crate::auth::role::Permission;
+use crate::core::middlewares::authorization::{authenticated, authorized, Access, AuthMethods};
+use crate::core::middlewares::csrf::csrf_protected;
+use crate::core::middlewares::rate_limit::client_ip;
 use crate::core::middlewares::with_env::with_env;
 use crate::Environment;
 
@@ -10,21 +17,87 @@ pub fn routes(env: Environment) -> BoxedFilter<(impl Reply,)> {
     let login_route = warp::path!("api" / "v1" / "login")
         .and(warp::post())
         .and(warp::body::json())
+        .and(client_ip(env.config.trusted_proxies.clone()))
+        .and(warp::header::optional::<String>("user-agent"))
         .and(with_env(env.clone()))
         .and_then(login_handler);
 
+    let login_mfa_route = warp::path!("api" / "v1" / "login" / "mfa")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(client_ip(env.config.trusted_proxies.clone()))
+        .and(with_env(env.clone()))
+        .and_then(login_mfa_handler);
+
     let logout_route = warp::path!("api" / "v1" / "logout")
         .and(warp::post())
-        .and(authenticated_from_cookie(env.clone()))
+        .and(authenticated(env.clone(), AuthMethods::session()))
         .and(with_env(env.clone()))
         .and_then(logout_handler);
 
     let renew_route = warp::path!("api" / "v1" / "token" / "renew")
         .and(warp::post())
-        .and(authenticated_from_cookie(env.clone()))
-        .and(with_env(env))
+        .and(csrf_protected(&env.config.cookie))
+        .and(warp::cookie::<String>(env.config.cookie.refresh_token_name()))
+        .and(with_env(env.clone()))
         .and_then(renew_handler);
 
-    let routes = login_route.or(logout_route).or(renew_route);
+    let list_sessions_route = warp::path!("api" / "v1" / "sessions")
+        .and(warp::get())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env.clone()))
+        .and_then(list_sessions_handler);
+
+    let revoke_session_route = warp::path!("api" / "v1" / "sessions" / String)
+        .and(warp::delete())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env.clone()))
+        .and_then(revoke_session_handler);
+
+    let revoke_all_sessions_route = warp::path!("api" / "v1" / "sessions")
+        .and(warp::delete())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env.clone()))
+        .and_then(revoke_all_sessions_handler);
+
+    let unlock_route = warp::path!("api" / "v1" / "admin" / "lockouts" / String)
+        .and(warp::delete())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(unlock_handler);
+
+    let jwks_route = warp::path!(".well-known" / "jwks.json")
+        .and(warp::get())
+        .and(with_env(env.clone()))
+        .and_then(jwks_handler);
+
+    let oidc_login_route = warp::path!("api" / "v1" / "oidc" / String / "login")
+        .and(warp::get())
+        .and(with_env(env.clone()))
+        .and_then(oidc_login_handler);
+
+    let oidc_callback_route = warp::path!("api" / "v1" / "oidc" / String / "callback")
+        .and(warp::get())
+        .and(warp::query())
+        .and(client_ip(env.config.trusted_proxies.clone()))
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(with_env(env))
+        .and_then(oidc_callback_handler);
+
+    let routes = login_route
+        .or(login_mfa_route)
+        .or(logout_route)
+        .or(renew_route)
+        .or(list_sessions_route)
+        .or(revoke_session_route)
+        .or(revoke_all_sessions_route)
+        .or(unlock_route)
+        .or(jwks_route)
+        .or(oidc_login_route)
+        .or(oidc_callback_route);
     routes.boxed()
 }
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+pub const USAGE: &str = "\
+usage: web_api_gateway [options]
+
+options:
+    --config <path>       config file, defaults to ./gateway.toml when it exists
+    --env-file <path>     env file, defaults to ./.env when it exists
+    --listen <address>    address to listen on, such as 127.0.0.1:3030
+    --set <key>=<value>   overrides any key of the config file
+    --check-config        validates the config and exits
+    --help                prints this message";
+
+/// Command line flags, they take precedence over the env and the config
+/// file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Args {
+    pub config_path: Option<String>,
+    pub env_file: Option<String>,
+    pub check_config: bool,
+    pub help: bool,
+    pub overrides: HashMap<String, String>,
+}
+
+impl Args {
+    /// Parses the flags after the name of the binary.
+    pub fn parse(args: impl IntoIterator<Item=String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-config" => parsed.check_config = true,
+                "--help" | "-h" => parsed.help = true,
+                "--config" => parsed.config_path = Some(value(&arg, args.next())?),
+                "--env-file" => parsed.env_file = Some(value(&arg, args.next())?),
+                "--listen" => {
+                    parsed
+                        .overrides
+                        .insert("listen_addr".to_string(), value(&arg, args.next())?);
+                }
+                "--set" => {
+                    let setting = value(&arg, args.next())?;
+                    let (key, value) = setting
+                        .split_once('=')
+                        .ok_or_else(|| format!("--set expects <key>=<value>, got {:?}.", setting))?;
+                    parsed
+                        .overrides
+                        .insert(key.trim().to_lowercase(), value.to_string());
+                }
+                _ => return Err(format!("unknown argument {:?}.", arg)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value
+        .filter(|value| !value.starts_with("--"))
+        .ok_or_else(|| format!("{} expects a value.", flag))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn it_can_parse_flags() {
+        let parsed = args(&[
+            "--config",
+            "gateway.toml",
+            "--listen",
+            "0.0.0.0:8080",
+            "--set",
+            "JWT_ISSUER=auth",
+            "--check-config",
+        ])
+        .unwrap();
+
+        assert!(parsed.check_config);
+        assert_eq!(parsed.config_path.as_deref(), Some("gateway.toml"));
+        assert_eq!(parsed.overrides["listen_addr"], "0.0.0.0:8080");
+        assert_eq!(parsed.overrides["jwt_issuer"], "auth");
+    }
+
+    #[test]
+    fn it_cannot_parse_unknown_or_incomplete_flags() {
+        assert!(args(&["--verbose"]).is_err());
+        assert!(args(&["--config"]).is_err());
+        assert!(args(&["--config", "--check-config"]).is_err());
+        assert!(args(&["--set", "jwt_issuer"]).is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use tracing::info;
+
+/// Security relevant events are logged under the `audit` target, so they
+/// can be routed to their own sink by the subscriber.
+pub fn audit(event: &str, subject: &str, actor: Option<&str>, ip: Option<&str>) {
+    info!(
+        target: "audit",
+        event,
+        subject,
+        actor = actor.unwrap_or("-"),
+        ip = ip.unwrap_or("-"),
+        "audit event {} of {}.",
+        event,
+        subject
+    );
+}
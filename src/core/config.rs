@@ -1,7 +1,57 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::auth::json::oidc::OidcConfig;
+use crate::auth::keys::KeyStore;
+use crate::core::args::Args;
+use crate::core::cookie::{CookieConfig, SameSite};
+use crate::core::cors::CorsConfig;
+use crate::proxy::json::config::{ProxyConfig, RateLimitConfig, RateLimitKey};
+
+const DEFAULT_CONFIG_PATH: &str = "./gateway.toml";
+const DEFAULT_ENV_FILE: &str = "./.env";
+
+const BOOL: &str = "true or false";
+const NUMBER: &str = "a positive integer";
+
+/// Settings of the gateway. Every key can be set in the config file, by
+/// the env variable of the same name in upper case, or by `--set`, each
+/// source overriding the previous one. Tables of the config file prefix
+/// their keys, `[postgres] host` is the key `postgres_host`.
 #[derive(Debug, Clone)]
 pub struct Config {
     pub debug: bool,
+    pub listen_addr: SocketAddr,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    pub trusted_proxies: Vec<IpAddr>,
     pub secret_key: String,
+    pub identity_signing_key: Option<String>,
+    pub jwt_algorithm: String,
+    pub jwt_keys_path: Option<String>,
+    pub jwt_active_kid: Option<String>,
+    pub jwt_issuer: String,
+    pub jwt_audience: String,
+    pub jwt_leeway_seconds: u64,
+    pub session_expired_seconds: usize,
+    pub access_token_expired_seconds: usize,
+    pub mfa_challenge_expired_seconds: usize,
+    pub oidc_state_expired_seconds: usize,
+    pub mfa_issuer: String,
+    pub login_max_user_failures: u32,
+    pub login_max_ip_failures: u32,
+    pub login_lockout_seconds: usize,
+    pub login_mfa_rate_limit: RateLimitConfig,
+    pub notifier_path: Option<String>,
+    pub password_pepper: Option<String>,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub cookie: CookieConfig,
+    pub cors: CorsConfig,
     pub postgres_host: String,
     pub postgres_database: String,
     pub postgres_username: String,
@@ -12,57 +62,478 @@ pub struct Config {
     pub redis_username: Option<String>,
     pub redis_password: String,
     pub redis_port: u16,
+    pub redis_max_connections: u32,
+    pub proxy: ProxyConfig,
+    pub oidc: OidcConfig,
 }
 
 impl Config {
-    pub fn new() -> Self {
-        let debug = dotenv::var("DEBUG")
-            .map(|x| x.parse::<bool>().unwrap_or(true))
-            .unwrap_or_else(|_| true);
+    /// Loads the config without flags.
+    pub fn new() -> Result<Self, ConfigErrors> {
+        Self::load(&Args::default())
+    }
+
+    /// Loads the env file and the config file, then layers the defaults,
+    /// the config file, the env and the flags of `args`.
+    pub fn load(args: &Args) -> Result<Self, ConfigErrors> {
+        let mut errors = vec![];
+
+        // dotenv keeps the variables which are already set, so the env of
+        // the process wins over the env file.
+        match args.env_file.clone().or_else(|| std::env::var("ENV_FILE").ok()) {
+            Some(path) => {
+                if let Err(e) = dotenv::from_path(&path) {
+                    errors.push(format!("can't read the env file {}: {}.", path, e));
+                }
+            }
+            None if Path::new(DEFAULT_ENV_FILE).exists() => {
+                if let Err(e) = dotenv::from_path(DEFAULT_ENV_FILE) {
+                    errors.push(format!("can't read the env file {}: {}.", DEFAULT_ENV_FILE, e));
+                }
+            }
+            None => {}
+        }
+
+        let config_path = args
+            .config_path
+            .clone()
+            .or_else(|| std::env::var("CONFIG_PATH").ok())
+            .or_else(|| Some(DEFAULT_CONFIG_PATH.to_string()).filter(|p| Path::new(p).exists()));
+        let file = match &config_path {
+            None => HashMap::new(),
+            Some(path) => read_config_file(path).unwrap_or_else(|e| {
+                errors.push(format!("can't read the config file {}: {}.", path, e));
+                HashMap::new()
+            }),
+        };
+
+        let mut loader = Loader {
+            overrides: args.overrides.clone(),
+            env: std::env::vars().collect(),
+            file,
+            read: HashSet::new(),
+            errors,
+        };
+        let config = Self::from_loader(&mut loader);
+
+        let mut errors = loader.unknown_keys(config_path.as_deref());
+        if let Err(ConfigErrors(invalid)) = config.validate() {
+            errors.extend(invalid);
+        }
 
-        if debug {
-            let _ = dotenv::from_path("./dev.env").expect("Can't find the dev.env.");
+        if errors.is_empty() {
+            Ok(config)
         } else {
-            let _ = dotenv::from_path("./prod.env").expect("Can't find the prod.env.");
-        }
-
-        let secret_key = dotenv::var("SECRET_KEY").expect("Can't read secret_key from env.");
-
-        let postgres_host =
-            dotenv::var("POSTGRES_HOST").expect("Can't read postgres_host from env.");
-        let postgres_username =
-            dotenv::var("POSTGRES_USER").expect("Can't read postgres_user from env.");
-        let postgres_password =
-            dotenv::var("POSTGRES_PASSWORD").expect("Can't read postgres_password from env.");
-        let postgres_port = dotenv::var("POSTGRES_PORT")
-            .expect("Can't read postgres_port from env.")
-            .parse::<u16>()
-            .expect("Can't parse the port to u16 type.");
-        let postgres_database =
-            dotenv::var("POSTGRES_DB").expect("Can't read postgres_db from env.");
-
-        let redis_host = dotenv::var("REDIS_HOST").expect("Can't read redis host from env.");
-        let redis_username = dotenv::var("REDIS_USERNAME").ok();
-        let redis_password =
-            dotenv::var("REDIS_PASSWORD").expect("Can't read redis password from env.");
-        let redis_port = dotenv::var("REDIS_PORT")
-            .expect("Can't read redis port from env.")
-            .parse::<u16>()
-            .unwrap_or(6379);
+            Err(ConfigErrors(errors))
+        }
+    }
+
+    fn from_loader(loader: &mut Loader) -> Self {
+        let debug = loader.parse_or("debug", BOOL, true);
+        let listen_addr = loader.parse_or(
+            "listen_addr",
+            "an address such as 127.0.0.1:3030",
+            SocketAddr::from(([127, 0, 0, 1], 3030)),
+        );
+        let tls_cert_path = loader.get("tls_cert_path");
+        let tls_key_path = loader.get("tls_key_path");
+        let trusted_proxies = loader.parse_list("trusted_proxies", "a list of ip addresses");
+
+        let secret_key = loader.required("secret_key");
+        let identity_signing_key = loader.get("identity_signing_key");
+        let jwt_algorithm = loader.get_or("jwt_algorithm", "HS256");
+        let jwt_keys_path = loader.get("jwt_keys_path");
+        let jwt_active_kid = loader.get("jwt_active_kid");
+        let jwt_issuer = loader.get_or("jwt_issuer", "gateway");
+        let jwt_audience = loader.get_or("jwt_audience", "gateway");
+        let jwt_leeway_seconds = loader.parse_or("jwt_leeway_seconds", NUMBER, 60);
+        let session_expired_seconds =
+            loader.parse_or("session_expired_seconds", NUMBER, 30 * 24 * 60 * 60);
+        let access_token_expired_seconds =
+            loader.parse_or("access_token_expired_seconds", NUMBER, 15 * 60);
+        let mfa_challenge_expired_seconds =
+            loader.parse_or("mfa_challenge_expired_seconds", NUMBER, 5 * 60);
+        let oidc_state_expired_seconds =
+            loader.parse_or("oidc_state_expired_seconds", NUMBER, 10 * 60);
+        let mfa_issuer = loader.get_or("mfa_issuer", "gateway");
+
+        let login_max_user_failures = loader.parse_or("login_max_user_failures", NUMBER, 5);
+        let login_max_ip_failures = loader.parse_or("login_max_ip_failures", NUMBER, 50);
+        let login_lockout_seconds = loader.parse_or("login_lockout_seconds", NUMBER, 15 * 60);
+        let login_mfa_rate_limit = RateLimitConfig {
+            key: RateLimitKey::Ip,
+            limit: loader.parse_or("login_mfa_rate_limit", NUMBER, 10),
+            window_seconds: loader.parse_or("login_mfa_rate_limit_window_seconds", NUMBER, 60),
+        };
+        let notifier_path = loader.get("notifier_path");
+
+        let password_pepper = loader.get("password_pepper");
+        let argon2_memory_kib = loader.parse_or("argon2_memory_kib", NUMBER, 19 * 1024);
+        let argon2_iterations = loader.parse_or("argon2_iterations", NUMBER, 2);
+        let argon2_parallelism = loader.parse_or("argon2_parallelism", NUMBER, 1);
+
+        let cookie = CookieConfig {
+            same_site: loader.parse_or("cookie_same_site", "strict, lax or none", SameSite::Lax),
+            secure: loader.parse_or("cookie_secure", BOOL, !debug),
+            host_prefix: loader.parse_or("cookie_host_prefix", BOOL, false),
+        };
+
+        let postgres_host = loader.required("postgres_host");
+        let postgres_username = loader.required("postgres_user");
+        let postgres_password = loader.required("postgres_password");
+        let postgres_port = loader.parse_or("postgres_port", NUMBER, 5432);
+        let postgres_database = loader.required("postgres_db");
+        let postgres_max_connections = loader.parse_or("postgres_max_connections", NUMBER, 10);
+
+        let redis_host = loader.required("redis_host");
+        let redis_username = loader.get("redis_username");
+        let redis_password = loader.required("redis_password");
+        let redis_port = loader.parse_or("redis_port", NUMBER, 6379);
+        let redis_max_connections = loader.parse_or("redis_max_connections", NUMBER, 10);
+
+        let proxy_config_path = loader.get_or("proxy_config_path", "./proxy.toml");
+        let proxy = ProxyConfig::from_path(proxy_config_path.as_str()).unwrap_or_else(|e| {
+            loader.error(format!("can't read the proxy config {}: {}.", proxy_config_path, e));
+            ProxyConfig::default()
+        });
+
+        let oidc = match loader.get("oidc_config_path") {
+            None => OidcConfig::default(),
+            Some(path) => OidcConfig::from_path(path.as_str()).unwrap_or_else(|e| {
+                loader.error(format!("can't read the oidc config {}: {}.", path, e));
+                OidcConfig::default()
+            }),
+        };
+
+        let cors = match loader.get("cors_config_path") {
+            None => CorsConfig::default(),
+            Some(path) => CorsConfig::from_path(path.as_str()).unwrap_or_else(|e| {
+                loader.error(format!("can't read the cors config {}: {}.", path, e));
+                CorsConfig::default()
+            }),
+        };
 
         Self {
             debug,
+            listen_addr,
+            tls_cert_path,
+            tls_key_path,
+            trusted_proxies,
             secret_key,
+            identity_signing_key,
+            jwt_algorithm,
+            jwt_keys_path,
+            jwt_active_kid,
+            jwt_issuer,
+            jwt_audience,
+            jwt_leeway_seconds,
+            session_expired_seconds,
+            access_token_expired_seconds,
+            mfa_challenge_expired_seconds,
+            oidc_state_expired_seconds,
+            mfa_issuer,
+            login_max_user_failures,
+            login_max_ip_failures,
+            login_lockout_seconds,
+            login_mfa_rate_limit,
+            notifier_path,
+            password_pepper,
+            argon2_memory_kib,
+            argon2_iterations,
+            argon2_parallelism,
+            cookie,
+            cors,
             postgres_host,
             postgres_database,
             postgres_username,
             postgres_password,
             postgres_port,
-            postgres_max_connections: 10,
+            postgres_max_connections,
             redis_host,
             redis_username,
             redis_password,
-            redis_port
+            redis_port,
+            redis_max_connections,
+            proxy,
+            oidc,
         }
     }
+
+    /// Checks the values which parsed but don't work together.
+    pub fn validate(&self) -> Result<(), ConfigErrors> {
+        let mut errors = vec![];
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !Path::new(path).is_file() {
+                        errors.push(format!("the tls file {} doesn't exist.", path));
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => errors.push("tls_cert_path and tls_key_path must be set together.".to_string()),
+        }
+
+        if let Err(e) = KeyStore::from_config(self) {
+            errors.push(format!("can't load the jwt keys: {}.", e));
+        }
+
+        for (key, seconds) in [
+            ("session_expired_seconds", self.session_expired_seconds),
+            ("access_token_expired_seconds", self.access_token_expired_seconds),
+            ("mfa_challenge_expired_seconds", self.mfa_challenge_expired_seconds),
+            ("oidc_state_expired_seconds", self.oidc_state_expired_seconds),
+        ] {
+            if seconds == 0 {
+                errors.push(format!("{} must be greater than 0.", key));
+            }
+        }
+        if self.login_mfa_rate_limit.window_seconds == 0 {
+            errors.push("login_mfa_rate_limit_window_seconds must be greater than 0.".to_string());
+        }
+        if self.access_token_expired_seconds > self.session_expired_seconds {
+            errors.push(
+                "access_token_expired_seconds can't exceed session_expired_seconds.".to_string(),
+            );
+        }
+
+        for (key, value) in [
+            ("login_max_user_failures", self.login_max_user_failures),
+            ("login_max_ip_failures", self.login_max_ip_failures),
+            ("login_mfa_rate_limit", self.login_mfa_rate_limit.limit),
+            ("argon2_iterations", self.argon2_iterations),
+            ("argon2_parallelism", self.argon2_parallelism),
+            ("postgres_max_connections", self.postgres_max_connections),
+            ("redis_max_connections", self.redis_max_connections),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0.", key));
+            }
+        }
+        // Argon2 needs 8 KiB of memory for every lane.
+        if self.argon2_memory_kib < 8 * self.argon2_parallelism {
+            errors.push("argon2_memory_kib must be at least 8 * argon2_parallelism.".to_string());
+        }
+
+        if let Err(e) = self.cookie.validate() {
+            errors.push(format!("invalid cookie config, {}", e));
+        }
+        if let Err(e) = self.cors.validate() {
+            errors.push(format!("invalid cors config, {}", e));
+        }
+        if let Err(invalid) = self.proxy.validate() {
+            errors.extend(invalid.into_iter().map(|e| format!("invalid proxy config, {}", e)));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigErrors(errors))
+        }
+    }
+}
+
+/// Every problem found while loading the config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the config is invalid:")?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Reads the keys of every source, recording what it couldn't parse
+/// instead of stopping at the first problem.
+struct Loader {
+    overrides: HashMap<String, String>,
+    env: HashMap<String, String>,
+    file: HashMap<String, String>,
+    read: HashSet<String>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn get(&mut self, key: &str) -> Option<String> {
+        self.read.insert(key.to_string());
+
+        self.overrides
+            .get(key)
+            .or_else(|| self.env.get(key.to_uppercase().as_str()))
+            .or_else(|| self.file.get(key))
+            .cloned()
+    }
+
+    fn get_or(&mut self, key: &str, default: &str) -> String {
+        self.get(key).unwrap_or_else(|| default.to_string())
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.get(key).unwrap_or_else(|| {
+            self.error(format!("{} is required.", key));
+            String::new()
+        })
+    }
+
+    fn parse_or<T: FromStr>(&mut self, key: &str, expected: &str, default: T) -> T {
+        match self.get(key) {
+            None => default,
+            Some(value) => value.trim().parse::<T>().unwrap_or_else(|_| {
+                self.error(format!("{} must be {}, got {:?}.", key, expected, value));
+                default
+            }),
+        }
+    }
+
+    /// A comma separated list.
+    fn parse_list<T: FromStr>(&mut self, key: &str, expected: &str) -> Vec<T> {
+        let value = match self.get(key) {
+            None => return vec![],
+            Some(value) => value,
+        };
+
+        let items = value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse::<T>())
+            .collect::<Result<Vec<_>, _>>();
+        items.unwrap_or_else(|_| {
+            self.error(format!("{} must be {}, got {:?}.", key, expected, value));
+            vec![]
+        })
+    }
+
+    fn error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
+    /// The errors so far, along with the keys of the config file which
+    /// were never read, a typo would silently keep the default otherwise.
+    fn unknown_keys(self, config_path: Option<&str>) -> Vec<String> {
+        let mut errors = self.errors;
+        let mut unknown = self
+            .file
+            .keys()
+            .filter(|key| !self.read.contains(key.as_str()))
+            .collect::<Vec<_>>();
+        unknown.sort();
+
+        errors.extend(unknown.into_iter().map(|key| {
+            format!("unknown key {} in the config file {}.", key, config_path.unwrap_or(""))
+        }));
+        errors
+    }
+}
+
+fn read_config_file(path: &str) -> anyhow::Result<HashMap<String, String>> {
+    let content = std::fs::read_to_string(path)?;
+    let value = content.parse::<toml::Value>()?;
+
+    let mut values = HashMap::new();
+    flatten("", &value, &mut values)?;
+    Ok(values)
+}
+
+fn flatten(
+    prefix: &str,
+    value: &toml::Value,
+    values: &mut HashMap<String, String>,
+) -> anyhow::Result<()> {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.to_lowercase()
+                } else {
+                    format!("{}_{}", prefix, key.to_lowercase())
+                };
+                flatten(key.as_str(), value, values)?;
+            }
+        }
+        toml::Value::Array(_) => anyhow::bail!("{} can't be an array", prefix),
+        toml::Value::String(value) => {
+            values.insert(prefix.to_string(), value.clone());
+        }
+        value => {
+            values.insert(prefix.to_string(), value.to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loader(file: &str, env: &[(&str, &str)], overrides: &[(&str, &str)]) -> Loader {
+        let mut values = HashMap::new();
+        flatten("", &file.parse::<toml::Value>().unwrap(), &mut values).unwrap();
+
+        Loader {
+            overrides: overrides
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            file: values,
+            read: HashSet::new(),
+            errors: vec![],
+        }
+    }
+
+    #[test]
+    fn it_can_layer_file_env_and_flags() {
+        let mut loader = loader(
+            r#"
+            jwt_issuer = "file"
+            jwt_audience = "file"
+            [postgres]
+            port = 6432
+            max_connections = 20
+            "#,
+            &[("JWT_ISSUER", "env"), ("JWT_AUDIENCE", "env")],
+            &[("jwt_issuer", "flag")],
+        );
+
+        assert_eq!(loader.get_or("jwt_issuer", "default"), "flag");
+        assert_eq!(loader.get_or("jwt_audience", "default"), "env");
+        assert_eq!(loader.get_or("mfa_issuer", "default"), "default");
+        assert_eq!(loader.parse_or("postgres_port", NUMBER, 5432u16), 6432);
+        assert_eq!(loader.parse_or("postgres_max_connections", NUMBER, 10u32), 20);
+        assert!(loader.unknown_keys(None).is_empty());
+    }
+
+    #[test]
+    fn it_can_report_every_problem() {
+        let mut loader = loader(
+            r#"
+            debug = "maybe"
+            jwt_isuer = "typo"
+            "#,
+            &[("POSTGRES_PORT", "port")],
+            &[],
+        );
+
+        assert!(loader.parse_or("debug", BOOL, true));
+        assert_eq!(loader.parse_or("postgres_port", NUMBER, 5432u16), 5432);
+        assert_eq!(loader.required("secret_key"), "");
+
+        let errors = ConfigErrors(loader.unknown_keys(Some("gateway.toml")));
+        assert_eq!(
+            errors.to_string(),
+            "the config is invalid:\
+            \n  - debug must be true or false, got \"maybe\".\
+            \n  - postgres_port must be a positive integer, got \"port\".\
+            \n  - secret_key is required.\
+            \n  - unknown key jwt_isuer in the config file gateway.toml."
+        );
+    }
 }
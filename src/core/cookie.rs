@@ -0,0 +1,141 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for SameSite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(format!("unknown same site {}.", s)),
+        }
+    }
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Attributes of the session cookies. With `host_prefix` the token and
+/// csrf cookies are named `__Host-`, so a subdomain can't overwrite them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CookieConfig {
+    pub same_site: SameSite,
+    pub secure: bool,
+    pub host_prefix: bool,
+}
+
+impl CookieConfig {
+    /// Browsers drop cookies which break these rules without a warning.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.host_prefix && !self.secure {
+            return Err("the __Host- prefix needs secure cookies.");
+        }
+        if self.same_site == SameSite::None && !self.secure {
+            return Err("same site none needs secure cookies.");
+        }
+        Ok(())
+    }
+
+    pub fn token_name(&self) -> &'static str {
+        if self.host_prefix {
+            "__Host-token"
+        } else {
+            "token"
+        }
+    }
+
+    // `__Host-` requires `path=/`, the refresh cookie is only sent to the
+    // renew endpoint.
+    pub fn refresh_token_name(&self) -> &'static str {
+        if self.host_prefix {
+            "__Secure-refresh_token"
+        } else {
+            "refresh_token"
+        }
+    }
+
+    pub fn csrf_token_name(&self) -> &'static str {
+        if self.host_prefix {
+            "__Host-csrf_token"
+        } else {
+            "csrf_token"
+        }
+    }
+
+    /// A `set-cookie` value. The csrf cookie isn't http only, the client
+    /// reads it to echo it in the csrf header.
+    pub fn build(
+        &self,
+        name: &str,
+        value: &str,
+        path: &str,
+        http_only: bool,
+        expired_at: DateTime<Utc>,
+        max_age: usize,
+    ) -> String {
+        let mut cookie = format!(
+            "{}={}; path={}; expires={}; max-age={}; SameSite={}",
+            name,
+            value,
+            path,
+            expired_at.to_rfc2822(),
+            max_age,
+            self.same_site.as_str()
+        );
+        if http_only {
+            cookie.push_str("; httpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_build_prefixed_cookie() {
+        let config = CookieConfig {
+            same_site: SameSite::Strict,
+            secure: true,
+            host_prefix: true,
+        };
+        let cookie = config.build(config.token_name(), "jwt", "/", true, Utc::now(), 60);
+
+        assert!(config.validate().is_ok());
+        assert!(cookie.starts_with("__Host-token=jwt; path=/;"));
+        assert!(cookie.ends_with("SameSite=Strict; httpOnly; Secure"));
+    }
+
+    #[test]
+    fn it_cannot_prefix_insecure_cookie() {
+        let config = CookieConfig {
+            same_site: SameSite::Lax,
+            secure: false,
+            host_prefix: true,
+        };
+
+        assert!(config.validate().is_err());
+        assert_eq!("none".parse::<SameSite>(), Ok(SameSite::None));
+    }
+}
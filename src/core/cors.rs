@@ -0,0 +1,225 @@
+use serde::Deserialize;
+
+use crate::core::middlewares::csrf::CSRF_HEADER;
+
+/// Cross origin settings of the gateway, read from a toml file.
+///
+/// `default` applies to every path, an entry of `routes` overrides some of
+/// its fields for a route group. The longest matching prefix wins.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CorsConfig {
+    #[serde(default)]
+    pub default: CorsPolicy,
+    #[serde(default)]
+    pub routes: Vec<CorsRouteConfig>,
+}
+
+impl CorsConfig {
+    pub fn from_path(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config = toml::from_str::<CorsConfig>(content.as_str())?;
+        Ok(config)
+    }
+
+    /// Every route group with the default applied, the longest prefix first.
+    pub fn policies(&self) -> Vec<(String, CorsPolicy)> {
+        let mut policies = self
+            .routes
+            .iter()
+            .map(|route| (route.prefix.clone(), route.apply(&self.default)))
+            .collect::<Vec<_>>();
+
+        policies.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        policies
+    }
+
+    /// A credentialed request can't be allowed from any origin, browsers
+    /// would send the cookies of the user to every site.
+    pub fn validate(&self) -> Result<(), String> {
+        let policies = std::iter::once(("default".to_string(), self.default.clone()))
+            .chain(self.policies());
+
+        for (name, policy) in policies {
+            if policy.allow_credentials && policy.allowed_origins.iter().any(|o| o == "*") {
+                return Err(format!("{} allows credentials from any origin.", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Origins may be `*`, or have one `*` in place of subdomains such as
+/// `https://*.example.com`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsPolicy {
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default = "default_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub exposed_headers: Vec<String>,
+    pub max_age_seconds: Option<u64>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+/// No origin is allowed until one is configured.
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: default_allowed_methods(),
+            allowed_headers: default_allowed_headers(),
+            exposed_headers: vec![],
+            max_age_seconds: None,
+            allow_credentials: false,
+        }
+    }
+}
+
+impl CorsPolicy {
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+
+        self.allowed_origins
+            .iter()
+            .any(|pattern| origin_matches(pattern.to_ascii_lowercase().as_str(), origin.as_str()))
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    pub fn allows_header(&self, header: &str) -> bool {
+        self.allowed_headers
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(header))
+    }
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    match pattern.split_once('*') {
+        None => pattern == origin,
+        Some((prefix, suffix)) => {
+            // Only subdomain labels can take the place of the `*`.
+            origin.len() > prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+                && origin[prefix.len()..origin.len() - suffix.len()]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        }
+    }
+}
+
+/// Fields left out keep the value of the default policy.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsRouteConfig {
+    pub prefix: String,
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    pub exposed_headers: Option<Vec<String>>,
+    pub max_age_seconds: Option<u64>,
+    pub allow_credentials: Option<bool>,
+}
+
+impl CorsRouteConfig {
+    fn apply(&self, default: &CorsPolicy) -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: self
+                .allowed_origins
+                .clone()
+                .unwrap_or_else(|| default.allowed_origins.clone()),
+            allowed_methods: self
+                .allowed_methods
+                .clone()
+                .unwrap_or_else(|| default.allowed_methods.clone()),
+            allowed_headers: self
+                .allowed_headers
+                .clone()
+                .unwrap_or_else(|| default.allowed_headers.clone()),
+            exposed_headers: self
+                .exposed_headers
+                .clone()
+                .unwrap_or_else(|| default.exposed_headers.clone()),
+            max_age_seconds: self.max_age_seconds.or(default.max_age_seconds),
+            allow_credentials: self.allow_credentials.unwrap_or(default.allow_credentials),
+        }
+    }
+}
+
+fn default_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .iter()
+        .map(|method| method.to_string())
+        .collect()
+}
+
+fn default_allowed_headers() -> Vec<String> {
+    ["content-type", "authorization", CSRF_HEADER]
+        .iter()
+        .map(|header| header.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> CorsConfig {
+        toml::from_str(
+            r#"
+            [default]
+            allowed_origins = ["https://app.example.com", "https://*.example.org"]
+            allow_credentials = true
+
+            [[routes]]
+            prefix = "/api/v1/public"
+            allowed_origins = ["*"]
+            allow_credentials = false
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_can_match_wildcard_origins() {
+        let policy = config().default;
+
+        assert!(policy.allows_origin("https://app.example.com"));
+        assert!(policy.allows_origin("https://a.b.example.org"));
+        assert!(!policy.allows_origin("https://example.org"));
+        assert!(!policy.allows_origin("https://evil.com/.example.org"));
+        assert!(!policy.allows_origin("http://app.example.com"));
+    }
+
+    #[test]
+    fn it_can_override_policy_of_route_group() {
+        let config = config();
+        let policies = config.policies();
+        let (prefix, public) = &policies[0];
+
+        assert!(config.validate().is_ok());
+        assert_eq!(prefix, "/api/v1/public");
+        assert!(public.allows_origin("https://anywhere.com"));
+        assert!(!public.allow_credentials);
+        assert!(public.allows_header("X-CSRF-Token"));
+    }
+
+    #[test]
+    fn it_cannot_allow_credentials_from_any_origin() {
+        let mut config = config();
+        config.routes[0].allow_credentials = None;
+
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,12 +1,25 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::{Config, RedisAuthRepository, PostgresUserRepository};
+use crate::{AppResult, Config, RedisAuthRepository, PostgresUserRepository};
+use crate::api_key::repo::PostgresApiKeyRepository;
+use crate::auth::keys::KeyStore;
+use crate::auth::oidc::OidcClient;
+use crate::core::middlewares::rate_limit::RedisRateLimiter;
+use crate::core::notifier::{LocalNotifier, Notifier};
+use crate::proxy::table::RouteTable;
 
 #[derive(Clone)]
 pub struct Environment {
     pub config: Config,
     pub auth_repo: Arc<RedisAuthRepository>,
     pub user_repo: Arc<PostgresUserRepository>,
+    pub api_key_repo: Arc<PostgresApiKeyRepository>,
+    pub rate_limiter: Arc<RedisRateLimiter>,
+    pub route_table: Arc<RouteTable>,
+    pub key_store: Arc<KeyStore>,
+    pub oidc_clients: Arc<HashMap<String, OidcClient>>,
+    pub notifier: Arc<dyn Notifier + Send + Sync>,
 }
 
 impl Environment {
@@ -14,11 +27,32 @@ impl Environment {
         config: Config,
         auth_repo: Arc<RedisAuthRepository>,
         user_repo: Arc<PostgresUserRepository>,
-    ) -> Self {
-        Self {
+        api_key_repo: Arc<PostgresApiKeyRepository>,
+        rate_limiter: Arc<RedisRateLimiter>,
+    ) -> AppResult<Self> {
+        let route_table = Arc::new(RouteTable::new(&config.proxy).map_err(anyhow::Error::msg)?);
+        let key_store = Arc::new(KeyStore::from_config(&config)?);
+        let oidc_clients = Arc::new(
+            config
+                .oidc
+                .providers
+                .iter()
+                .map(|provider| (provider.name.clone(), OidcClient::new(provider.clone())))
+                .collect(),
+        );
+        let notifier: Arc<dyn Notifier + Send + Sync> =
+            Arc::new(LocalNotifier::new(config.notifier_path.clone()));
+
+        Ok(Self {
             config,
             auth_repo,
             user_repo,
-        }
+            api_key_repo,
+            rate_limiter,
+            route_table,
+            key_store,
+            oidc_clients,
+            notifier,
+        })
     }
 }
@@ -3,19 +3,53 @@ use serde::Serialize;
 #[derive(Debug, PartialEq)]
 pub enum AppError {
     AuthorizeFailed,
+    Forbidden,
     DatabaseError,
     HashPasswordFailed,
     UserNotExist,
+    UserAlreadyExist,
+    UserDisabled,
+    ValidationFailed(Vec<FieldError>),
+    ProviderNotExist,
+    ApiKeyNotExist,
+    InvalidMfaCode,
+    MfaNotEnrolled,
+    MfaAlreadyEnabled,
     TokenNotExist,
-    TokenIsExpired
+    TokenIsExpired,
+    CsrfTokenMismatch,
+    UpstreamUnavailable,
+    UpstreamRequestFailed,
+    CircuitOpen,
+    UpstreamTimeout,
+    RateLimited { limit: u32, retry_after: u64 },
+    LoginLocked { retry_after: u64 },
 }
 
 impl warp::reject::Reject for AppError {}
 
+/// An invalid field of a request body.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     code: u16,
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
 }
 
 impl From<(u16, &str)> for ErrorResponse {
@@ -23,6 +57,7 @@ impl From<(u16, &str)> for ErrorResponse {
         Self {
             code: e.0,
             message: e.1.to_string(),
+            fields: vec![],
         }
     }
 }
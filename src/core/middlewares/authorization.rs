@@ -1,51 +1,255 @@
+use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::Validation;
 use uuid::Uuid;
 use warp::{Filter, Rejection};
-use warp::http::{HeaderMap, HeaderValue};
+use warp::http::Method;
 
 use crate::{Environment, WebResult};
+use crate::api_key::handlers::verify_api_key;
 use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::keys::KeyStore;
 use crate::auth::repo::AuthRepository;
+use crate::auth::role::{Permission, Role};
+use crate::core::config::Config;
+use crate::core::cookie::CookieConfig;
 use crate::core::error::AppError;
+use crate::core::middlewares::csrf::{check_csrf, CSRF_HEADER};
 
 const BEARER: &str = "Bearer ";
+/// Machine clients send their api key in this header.
+pub const API_KEY_HEADER: &str = "x-api-key";
 
-pub fn authenticated_from_header(
+/// A way a request can authenticate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthMethod {
+    /// A session token in the `Authorization: Bearer` header.
+    Header,
+    /// A session token in the `token` cookie.
+    Cookie,
+    /// An api key in the `x-api-key` header.
+    ApiKey,
+}
+
+/// The methods a route accepts, in the order they are tried. The first
+/// method with a credential decides, an invalid credential doesn't fall
+/// through to the next method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthMethods(Vec<AuthMethod>);
+
+impl AuthMethods {
+    pub fn new(methods: Vec<AuthMethod>) -> Self {
+        Self(methods)
+    }
+
+    /// Session tokens only, for routes acting on the session or the account
+    /// of the user.
+    pub fn session() -> Self {
+        Self(vec![AuthMethod::Header, AuthMethod::Cookie])
+    }
+
+    pub fn all() -> Self {
+        Self(vec![AuthMethod::ApiKey, AuthMethod::Header, AuthMethod::Cookie])
+    }
+}
+
+/// The credentials sent with a request, not verified yet. The method and
+/// the csrf tokens are kept to check a request authenticated by the cookie.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub bearer: Option<String>,
+    pub cookie: Option<String>,
+    pub api_key: Option<String>,
+    pub method: Method,
+    pub csrf_header: Option<String>,
+    pub csrf_cookie: Option<String>,
+}
+
+impl Credentials {
+    /// The credential of the first method which has one.
+    fn select(&self, methods: &AuthMethods) -> Option<(AuthMethod, &String)> {
+        methods.0.iter().find_map(|method| {
+            let credential = match method {
+                AuthMethod::Header => self.bearer.as_ref(),
+                AuthMethod::Cookie => self.cookie.as_ref(),
+                AuthMethod::ApiKey => self.api_key.as_ref(),
+            };
+            credential.map(|credential| (*method, credential))
+        })
+    }
+}
+
+pub fn credentials(
+    cookie: &CookieConfig,
+) -> impl Filter<Extract=(Credentials, ), Error=Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(cookie.token_name()))
+        .and(warp::header::optional::<String>(API_KEY_HEADER))
+        .and(warp::method())
+        .and(warp::header::optional::<String>(CSRF_HEADER))
+        .and(warp::cookie::optional::<String>(cookie.csrf_token_name()))
+        .map(
+            |authorization: Option<String>, cookie, api_key, method, csrf_header, csrf_cookie| {
+                Credentials {
+                    bearer: authorization
+                        .and_then(|value| value.strip_prefix(BEARER).map(str::to_owned)),
+                    cookie,
+                    api_key,
+                    method,
+                    csrf_header,
+                    csrf_cookie,
+                }
+            },
+        )
+}
+
+pub fn authenticated(
     env: Environment,
-) -> impl Filter<Extract=(Claims, ), Error=Rejection> + Clone {
-    warp::header::headers_cloned()
-        .map(move |headers: HeaderMap<HeaderValue>| headers)
-        .and_then(jwt_from_header)
+    methods: AuthMethods,
+) -> impl Filter<Extract=(Principal, ), Error=Rejection> + Clone {
+    credentials(&env.config.cookie)
+        .and(warp::any().map(move || methods.clone()))
         .and(warp::any().map(move || env.clone()))
-        .and_then(authorize)
+        .and_then(authenticate)
 }
 
-pub fn authenticated_from_cookie(
+/// For public routes which personalize their response, an anonymous
+/// request or an invalid credential yields `None`.
+pub fn optional_authentication(
     env: Environment,
-) -> impl Filter<Extract=(Claims, ), Error=Rejection> + Clone {
-    warp::cookie::<String>("token")
+    methods: AuthMethods,
+) -> impl Filter<Extract=(Option<Principal>, ), Error=Rejection> + Clone {
+    credentials(&env.config.cookie)
+        .and(warp::any().map(move || methods.clone()))
         .and(warp::any().map(move || env.clone()))
-        .and_then(authorize)
+        .and_then(authenticate_optionally)
+}
+
+pub async fn authenticate(
+    credentials: Credentials,
+    methods: AuthMethods,
+    env: Environment,
+) -> WebResult<Principal> {
+    verify(&credentials, &methods, env)
+        .await?
+        .ok_or_else(|| warp::reject::custom(AppError::TokenNotExist))
+}
+
+pub async fn authenticate_optionally(
+    credentials: Credentials,
+    methods: AuthMethods,
+    env: Environment,
+) -> WebResult<Option<Principal>> {
+    // A stale cookie mustn't break a public page, a cookie of a request
+    // failing the csrf check is ignored the same way.
+    Ok(verify(&credentials, &methods, env).await.ok().flatten())
+}
+
+async fn verify(
+    credentials: &Credentials,
+    methods: &AuthMethods,
+    env: Environment,
+) -> WebResult<Option<Principal>> {
+    match credentials.select(methods) {
+        None => Ok(None),
+        Some((AuthMethod::ApiKey, key)) => authorize_api_key(key.clone(), env).await.map(Some),
+        Some((method, jwt)) => {
+            // Browsers attach the cookie to cross site requests, the header
+            // and the api key can only be set by the client itself.
+            if method == AuthMethod::Cookie {
+                check_csrf(
+                    &credentials.method,
+                    credentials.csrf_header.as_deref(),
+                    credentials.csrf_cookie.as_deref(),
+                )
+                .map_err(warp::reject::custom)?;
+            }
+
+            authorize(jwt.clone(), env)
+                .await
+                .map(|claims| Some(Principal::User(claims)))
+        }
+    }
 }
 
-async fn authorize(jwt: String, env: Environment) -> WebResult<Claims> {
-    let mut validation_config = Validation::default();
-    validation_config.validate_exp = false;
+/// Roles and permissions required by a route, an empty requirement lets
+/// every authenticated user through, but no api key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Access {
+    pub roles: Vec<Role>,
+    pub permissions: Vec<Permission>,
+}
+
+impl Access {
+    pub fn roles(roles: Vec<Role>) -> Self {
+        Self {
+            roles,
+            permissions: vec![],
+        }
+    }
+
+    pub fn permission(permission: Permission) -> Self {
+        Self {
+            roles: vec![],
+            permissions: vec![permission],
+        }
+    }
+
+    /// The role of the claims must be one of `roles`, and the role must
+    /// grant every permission of `permissions`. Claims of an api key must
+    /// also have every permission in their scopes, so a key is only let
+    /// through where a permission is required.
+    pub fn check(&self, claims: &Claims) -> Result<(), AppError> {
+        if let Some(scopes) = &claims.scopes {
+            if self.permissions.is_empty()
+                || !self.permissions.iter().all(|p| scopes.contains(p))
+            {
+                return Err(AppError::Forbidden);
+            }
+        }
+
+        if self.roles.is_empty() && self.permissions.is_empty() {
+            return Ok(());
+        }
+
+        let role = Role::try_from(claims.role)?;
+
+        if !self.roles.is_empty() && !self.roles.contains(&role) {
+            return Err(AppError::Forbidden);
+        }
 
-    let claims = decode::<Claims>(
-        jwt.as_str(),
-        &DecodingKey::from_secret(env.config.secret_key.as_bytes()),
-        &validation_config,
-    );
+        if !self.permissions.iter().all(|p| role.has_permission(*p)) {
+            return Err(AppError::Forbidden);
+        }
 
-    if claims.is_err() {
-        return Err(warp::reject::custom(AppError::AuthorizeFailed));
+        Ok(())
     }
+}
+
+pub fn authorized(
+    env: Environment,
+    methods: AuthMethods,
+    access: Access,
+) -> impl Filter<Extract=(Principal, ), Error=Rejection> + Clone {
+    authenticated(env, methods)
+        .and(warp::any().map(move || access.clone()))
+        .and_then(check_access)
+}
+
+async fn check_access(principal: Principal, access: Access) -> WebResult<Principal> {
+    access
+        .check(principal.claims())
+        .map(|_| principal)
+        .map_err(warp::reject::custom)
+}
 
-    let claims = claims.unwrap().claims;
+pub async fn authorize(jwt: String, env: Environment) -> WebResult<Claims> {
+    let claims = decode_claims(jwt.as_str(), &env.config, &env.key_store)
+        .map_err(warp::reject::custom)?;
 
     if check_is_expired(&claims, env.auth_repo) {
         return Err(warp::reject::custom(AppError::TokenIsExpired));
@@ -54,26 +258,177 @@ async fn authorize(jwt: String, env: Environment) -> WebResult<Claims> {
     Ok(claims)
 }
 
+pub async fn authorize_api_key(key: String, env: Environment) -> WebResult<Principal> {
+    verify_api_key(
+        &*env.api_key_repo,
+        &*env.user_repo,
+        &*env.auth_repo,
+        &env.config,
+        key.as_str(),
+    )
+    .await
+    .map_err(warp::reject::custom)
+}
+
+/// Verifies the signature and the registered claims of the token, every
+/// time claim is checked with `jwt_leeway_seconds` of clock skew.
+fn decode_claims(jwt: &str, config: &Config, key_store: &KeyStore) -> Result<Claims, AppError> {
+    let mut validation_config = Validation::new(key_store.algorithm());
+    validation_config.leeway = config.jwt_leeway_seconds;
+    validation_config.validate_nbf = true;
+    validation_config.set_issuer(&[config.jwt_issuer.as_str()]);
+    validation_config.set_audience(&[config.jwt_audience.as_str()]);
+    validation_config.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+
+    let claims = key_store
+        .verify(jwt, &validation_config)
+        .map_err(|e| match e.kind() {
+            ErrorKind::ExpiredSignature => AppError::TokenIsExpired,
+            _ => AppError::AuthorizeFailed,
+        })?
+        .claims;
+
+    // A token issued in the future was signed by a key holder with a broken
+    // clock, or was forged to outlive the rotation.
+    let now = chrono::Utc::now().timestamp() as u64;
+    if claims.iat as u64 > now + config.jwt_leeway_seconds {
+        return Err(AppError::AuthorizeFailed);
+    }
+
+    Ok(claims)
+}
+
+/// A token is dead once its session is revoked, or its user is disabled.
 fn check_is_expired(claims: &Claims, auth_repo: Arc<impl AuthRepository>) -> bool {
-    Uuid::from_str(claims.sub.as_str())
-        .map(|uuid| auth_repo.get(uuid))
-        .ok()
-        .flatten()
-        .is_none()
-}
-
-async fn jwt_from_header(headers: HeaderMap<HeaderValue>) -> WebResult<String> {
-    let header = headers
-        .get(warp::http::header::AUTHORIZATION)
-        .and_then(|value| std::str::from_utf8(value.as_bytes()).ok());
-
-    return match header {
-        None => Err(warp::reject::custom(AppError::TokenNotExist)),
-        Some(value) => {
-            if !value.starts_with(BEARER) {
-                return Err(warp::reject::custom(AppError::TokenNotExist));
-            }
-            Ok(value.trim_start_matches(BEARER).to_owned())
-        }
-    };
+    let user_id = Uuid::from_str(claims.sub.as_str()).ok();
+
+    let session = auth_repo
+        .get(claims.jti.as_str())
+        .filter(|session| Some(session.user_id) == user_id);
+
+    match session {
+        Some(session) => auth_repo.is_user_blocked(session.user_id),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jsonwebtoken::Algorithm;
+
+    use crate::auth::json::session::{Session, SessionMetadata};
+    use crate::auth::repo::MockAuthRepository;
+
+    use super::*;
+
+    fn sign(key_store: &KeyStore, config: &Config, exp: i64, audience: &str) -> String {
+        let mut claims = Claims::new(
+            uuid::Uuid::new_v4().to_string(),
+            (chrono::Utc::now().timestamp() + exp) as usize,
+            0,
+            uuid::Uuid::new_v4().to_string(),
+        );
+        claims.iss = config.jwt_issuer.clone();
+        claims.aud = audience.to_string();
+        key_store.sign(&claims).unwrap()
+    }
+
+    #[test]
+    fn it_can_decode_claims() {
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+        let token = sign(&key_store, &config, 60, config.jwt_audience.as_str());
+
+        assert!(decode_claims(token.as_str(), &config, &key_store).is_ok());
+    }
+
+    #[test]
+    fn it_cannot_decode_expired_claims() {
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+        let exp = -(config.jwt_leeway_seconds as i64) - 60;
+        let token = sign(&key_store, &config, exp, config.jwt_audience.as_str());
+
+        assert_eq!(
+            decode_claims(token.as_str(), &config, &key_store).unwrap_err(),
+            AppError::TokenIsExpired
+        );
+    }
+
+    #[test]
+    fn it_cannot_decode_claims_of_other_audience() {
+        let config = Config::new().unwrap();
+        let key_store = KeyStore::from_secret(Algorithm::HS256, config.secret_key.as_str());
+        let token = sign(&key_store, &config, 60, "billing");
+
+        assert_eq!(
+            decode_claims(token.as_str(), &config, &key_store).unwrap_err(),
+            AppError::AuthorizeFailed
+        );
+    }
+
+    #[test]
+    fn it_can_expire_token_of_blocked_user() {
+        let user_id = uuid::Uuid::new_v4();
+        let session = Session::new(user_id, SessionMetadata::default());
+        let claims = Claims::new(user_id.to_string(), 0, 0, session.id.clone());
+
+        let mut auth_mock_repo = MockAuthRepository::new();
+        auth_mock_repo
+            .expect_get()
+            .returning(move |_| Some(session.clone()));
+        auth_mock_repo.expect_is_user_blocked().returning(|_| true);
+
+        assert!(check_is_expired(&claims, Arc::new(auth_mock_repo)));
+    }
+
+    #[test]
+    fn it_can_select_credential_by_method_order() {
+        let credentials = Credentials {
+            cookie: Some("jwt".to_string()),
+            api_key: Some("key".to_string()),
+            ..Credentials::default()
+        };
+        let key = "key".to_string();
+        let jwt = "jwt".to_string();
+
+        assert_eq!(
+            credentials.select(&AuthMethods::all()),
+            Some((AuthMethod::ApiKey, &key))
+        );
+        assert_eq!(
+            credentials.select(&AuthMethods::session()),
+            Some((AuthMethod::Cookie, &jwt))
+        );
+        assert_eq!(
+            credentials.select(&AuthMethods::new(vec![AuthMethod::Header])),
+            None
+        );
+    }
+
+    #[test]
+    fn it_can_limit_access_to_scopes() {
+        let access = Access::permission(Permission::ReadUsers);
+        let mut claims = Claims::new("boris".to_string(), 0, Role::Admin as u8, "key".to_string());
+
+        claims.scopes = Some(vec![Permission::ReadUsers]);
+        assert!(access.check(&claims).is_ok());
+
+        claims.scopes = Some(vec![Permission::ManageUpstreams]);
+        assert_eq!(access.check(&claims).unwrap_err(), AppError::Forbidden);
+    }
+
+    #[test]
+    fn it_cannot_pass_api_key_without_required_permission() {
+        let mut claims = Claims::new("boris".to_string(), 0, Role::Admin as u8, "key".to_string());
+
+        assert!(Access::default().check(&claims).is_ok());
+
+        claims.scopes = Some(vec![]);
+        assert_eq!(Access::default().check(&claims).unwrap_err(), AppError::Forbidden);
+        assert_eq!(
+            Access::roles(vec![Role::Admin]).check(&claims).unwrap_err(),
+            AppError::Forbidden
+        );
+    }
 }
@@ -0,0 +1,229 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use warp::filters::path::FullPath;
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::core::cors::{CorsConfig, CorsPolicy};
+
+const ALLOW_ORIGIN: &str = "access-control-allow-origin";
+const ALLOW_METHODS: &str = "access-control-allow-methods";
+const ALLOW_HEADERS: &str = "access-control-allow-headers";
+const ALLOW_CREDENTIALS: &str = "access-control-allow-credentials";
+const EXPOSE_HEADERS: &str = "access-control-expose-headers";
+const MAX_AGE: &str = "access-control-max-age";
+
+/// The cors policies of the gateway, matched by path prefix like the
+/// proxy routes.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    default: CorsPolicy,
+    routes: Vec<(String, CorsPolicy)>,
+}
+
+impl Cors {
+    pub fn new(config: &CorsConfig) -> Self {
+        Self {
+            default: config.default.clone(),
+            routes: config.policies(),
+        }
+    }
+
+    pub fn policy(&self, path: &str) -> &CorsPolicy {
+        self.routes
+            .iter()
+            .find(|(prefix, _)| {
+                prefix == "/"
+                    || path == prefix
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .map(|rest| rest.starts_with('/'))
+                        .unwrap_or(false)
+            })
+            .map(|(_, policy)| policy)
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Answers preflight requests of every path, proxied ones included, so
+/// they never reach an upstream.
+pub fn preflight(
+    cors: Arc<Cors>,
+) -> impl Filter<Extract=(Response, ), Error=Rejection> + Clone {
+    warp::options()
+        .and(warp::path::full())
+        .and(warp::header::<String>("origin"))
+        .and(warp::header::<String>("access-control-request-method"))
+        .and(warp::header::optional::<String>("access-control-request-headers"))
+        .map(move |path: FullPath, origin: String, method: String, headers: Option<String>| {
+            preflight_response(cors.policy(path.as_str()), &origin, &method, headers.as_deref())
+        })
+}
+
+fn preflight_response(
+    policy: &CorsPolicy,
+    origin: &str,
+    method: &str,
+    headers: Option<&str>,
+) -> Response {
+    let headers_allowed = headers
+        .map(|headers| {
+            headers
+                .split(',')
+                .map(str::trim)
+                .filter(|header| !header.is_empty())
+                .all(|header| policy.allows_header(header))
+        })
+        .unwrap_or(true);
+
+    if !policy.allows_origin(origin) || !policy.allows_method(method) || !headers_allowed {
+        let mut response = warp::reply::with_status("", StatusCode::FORBIDDEN).into_response();
+        vary_by_origin(response.headers_mut());
+        return response;
+    }
+
+    let mut response = warp::reply::with_status("", StatusCode::NO_CONTENT).into_response();
+    let response_headers = response.headers_mut();
+    vary_by_origin(response_headers);
+    allow_origin(response_headers, policy, origin);
+    insert(response_headers, ALLOW_METHODS, policy.allowed_methods.join(", "));
+    insert(response_headers, ALLOW_HEADERS, policy.allowed_headers.join(", "));
+    if let Some(max_age) = policy.max_age_seconds {
+        response_headers.insert(MAX_AGE, HeaderValue::from(max_age));
+    }
+    response
+}
+
+/// Adds the cors headers to the responses of `filter`. It should be
+/// recovered already, so that error responses get them too.
+pub fn with_cors<F, R>(
+    cors: Arc<Cors>,
+    filter: F,
+) -> impl Filter<Extract=(Response, ), Error=Rejection> + Clone
+where
+    F: Filter<Extract=(R, ), Error=Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::path::full()
+        .and(warp::header::optional::<String>("origin"))
+        .and(filter)
+        .map(move |path: FullPath, origin: Option<String>, reply: R| {
+            let mut response = reply.into_response();
+            // The gateway decides, whatever an upstream answered.
+            let headers = response.headers_mut();
+            for name in [ALLOW_ORIGIN, ALLOW_CREDENTIALS, EXPOSE_HEADERS] {
+                headers.remove(name);
+            }
+            vary_by_origin(headers);
+
+            if let Some(origin) = origin {
+                let policy = cors.policy(path.as_str());
+                if policy.allows_origin(&origin) {
+                    allow_origin(headers, policy, &origin);
+                    if !policy.exposed_headers.is_empty() {
+                        insert(headers, EXPOSE_HEADERS, policy.exposed_headers.join(", "));
+                    }
+                }
+            }
+            response
+        })
+}
+
+// The origin is echoed rather than `*`, and denied origins get no headers,
+// so a cache must keep every response apart by origin, even one to a
+// request without it.
+fn vary_by_origin(headers: &mut HeaderMap) {
+    headers.append("vary", HeaderValue::from_static("origin"));
+}
+
+fn allow_origin(headers: &mut HeaderMap, policy: &CorsPolicy, origin: &str) {
+    insert(headers, ALLOW_ORIGIN, origin.to_string());
+    if policy.allow_credentials {
+        headers.insert(ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: &'static str, value: String) {
+    if let Ok(value) = HeaderValue::from_str(value.as_str()) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::cors::CorsRouteConfig;
+
+    use super::*;
+
+    fn cors() -> Cors {
+        let config = CorsConfig {
+            default: CorsPolicy {
+                allowed_origins: vec!["https://app.example.com".to_string()],
+                allow_credentials: true,
+                max_age_seconds: Some(600),
+                ..CorsPolicy::default()
+            },
+            routes: vec![CorsRouteConfig {
+                prefix: "/api/v1/public".to_string(),
+                allowed_origins: Some(vec!["*".to_string()]),
+                allowed_methods: Some(vec!["GET".to_string()]),
+                allowed_headers: None,
+                exposed_headers: None,
+                max_age_seconds: None,
+                allow_credentials: Some(false),
+            }],
+        };
+        Cors::new(&config)
+    }
+
+    #[test]
+    fn it_can_answer_preflight_by_route_group() {
+        let cors = cors();
+
+        let response = preflight_response(
+            cors.policy("/api/v1/users"),
+            "https://app.example.com",
+            "DELETE",
+            Some("Content-Type, X-CSRF-Token"),
+        );
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[ALLOW_ORIGIN], "https://app.example.com");
+        assert_eq!(response.headers()[ALLOW_CREDENTIALS], "true");
+        assert_eq!(response.headers()[MAX_AGE], "600");
+
+        let response = preflight_response(
+            cors.policy("/api/v1/public/posts"),
+            "https://elsewhere.com",
+            "DELETE",
+            None,
+        );
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().get(ALLOW_ORIGIN).is_none());
+    }
+
+    #[test]
+    fn it_cannot_match_route_group_by_partial_segment() {
+        let cors = cors();
+
+        assert!(cors.policy("/api/v1/public").allows_origin("https://elsewhere.com"));
+        assert!(!cors.policy("/api/v1/publicity").allows_origin("https://elsewhere.com"));
+    }
+
+    #[test]
+    fn it_can_vary_by_origin_when_origin_is_denied() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let filter = with_cors(Arc::new(cors()), warp::any().map(|| "ok"));
+
+        let response = runtime.block_on(
+            warp::test::request()
+                .path("/api/v1/users")
+                .header("origin", "https://elsewhere.com")
+                .reply(&filter),
+        );
+
+        assert_eq!(response.headers()["vary"], "origin");
+        assert!(response.headers().get(ALLOW_ORIGIN).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+use warp::{Filter, Rejection};
+use warp::http::Method;
+
+use crate::auth::handlers::create_random_token;
+use crate::core::cookie::CookieConfig;
+use crate::core::error::AppError;
+use crate::core::util::constant_time_eq;
+use crate::WebResult;
+
+/// Browsers only attach cookies, so a cross site form can't set this
+/// header to the value of the csrf cookie.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+pub fn create_csrf_token() -> String {
+    create_random_token()
+}
+
+/// Rejects a state changing request whose csrf header doesn't match the
+/// csrf cookie, for routes authenticated by a cookie outside of
+/// `authenticated`.
+pub fn csrf_protected(cookie: &CookieConfig) -> impl Filter<Extract=(), Error=Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>(CSRF_HEADER))
+        .and(warp::cookie::optional::<String>(cookie.csrf_token_name()))
+        .and_then(csrf)
+        .untuple_one()
+}
+
+async fn csrf(method: Method, header: Option<String>, cookie: Option<String>) -> WebResult<()> {
+    check_csrf(&method, header.as_deref(), cookie.as_deref()).map_err(warp::reject::custom)
+}
+
+/// Double submit check, safe methods pass without a token.
+pub fn check_csrf(
+    method: &Method,
+    header: Option<&str>,
+    cookie: Option<&str>,
+) -> Result<(), AppError> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE) {
+        return Ok(());
+    }
+
+    match (header, cookie) {
+        (Some(header), Some(cookie))
+            if !cookie.is_empty() && constant_time_eq(header.as_bytes(), cookie.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(AppError::CsrfTokenMismatch),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_check_csrf_of_unsafe_methods_only() {
+        assert!(check_csrf(&Method::GET, None, None).is_ok());
+        assert!(check_csrf(&Method::POST, Some("token"), Some("token")).is_ok());
+        assert_eq!(
+            check_csrf(&Method::DELETE, Some("token"), Some("other")),
+            Err(AppError::CsrfTokenMismatch)
+        );
+        assert_eq!(
+            check_csrf(&Method::PATCH, None, Some("token")),
+            Err(AppError::CsrfTokenMismatch)
+        );
+    }
+}
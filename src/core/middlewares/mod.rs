@@ -1,2 +1,5 @@
 pub mod authorization;
+pub mod cors;
+pub mod csrf;
+pub mod rate_limit;
 pub mod with_env;
\ No newline at end of file
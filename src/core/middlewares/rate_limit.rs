@@ -0,0 +1,216 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use r2d2_redis::{r2d2, redis, RedisConnectionManager};
+use tracing::warn;
+use warp::http::{HeaderValue, Response};
+use warp::Filter;
+
+use crate::auth::json::claims::Claims;
+use crate::core::error::AppError;
+use crate::proxy::json::config::{RateLimitConfig, RateLimitKey};
+
+// Sliding window log, every request is a member of a sorted set scored by
+// its timestamp. Running it as a script keeps it atomic across replicas.
+const SLIDING_WINDOW_SCRIPT: &str = r"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], 0, tonumber(ARGV[1]) - tonumber(ARGV[2]))
+local count = redis.call('ZCARD', KEYS[1])
+if count < tonumber(ARGV[3]) then
+    redis.call('ZADD', KEYS[1], ARGV[1], ARGV[4])
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return {1, count + 1, 0}
+end
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+return {0, count, math.floor(tonumber(oldest[2]) + tonumber(ARGV[2]) - tonumber(ARGV[1]))}
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_seconds: u64,
+}
+
+impl RateLimitStatus {
+    pub fn apply<T>(&self, response: &mut Response<T>) {
+        let headers = response.headers_mut();
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(self.reset_seconds));
+    }
+}
+
+#[derive(Clone)]
+pub struct RedisRateLimiter {
+    connection_pool: r2d2::Pool<RedisConnectionManager>,
+}
+
+impl RedisRateLimiter {
+    pub fn new(pool: r2d2::Pool<RedisConnectionManager>) -> Self {
+        Self {
+            connection_pool: pool,
+        }
+    }
+
+    /// Counts a request of `subject` against the limit of `scope`.
+    ///
+    /// Redis being unavailable doesn't block the traffic, the request is let
+    /// through and a warning is logged.
+    pub fn check(
+        &self,
+        scope: &str,
+        subject: &str,
+        config: &RateLimitConfig,
+    ) -> Result<RateLimitStatus, AppError> {
+        let window_ms = config.window_seconds * 1000;
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let key = format!("rate_limit: {}: {}", scope, subject);
+        let member = format!("{}-{}", now_ms, uuid::Uuid::new_v4());
+
+        let result = self
+            .connection_pool
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                redis::Script::new(SLIDING_WINDOW_SCRIPT)
+                    .key(key.as_str())
+                    .arg(now_ms)
+                    .arg(window_ms)
+                    .arg(config.limit)
+                    .arg(member)
+                    .invoke::<(u8, u32, u64)>(&mut *conn)
+                    .map_err(|e| e.to_string())
+            });
+
+        match result {
+            Ok((1, count, _)) => Ok(RateLimitStatus {
+                limit: config.limit,
+                remaining: config.limit.saturating_sub(count),
+                reset_seconds: config.window_seconds,
+            }),
+            Ok((_, _, retry_after_ms)) => Err(AppError::RateLimited {
+                limit: config.limit,
+                retry_after: ((retry_after_ms + 999) / 1000).max(1),
+            }),
+            Err(e) => {
+                warn!("rate limit of {} is skipped: {}", key, e);
+                Ok(RateLimitStatus {
+                    limit: config.limit,
+                    remaining: config.limit,
+                    reset_seconds: config.window_seconds,
+                })
+            }
+        }
+    }
+}
+
+/// The client ip. `X-Forwarded-For` is only read when the request comes
+/// from one of `trusted_proxies`, anyone else could forge it.
+pub fn client_ip(
+    trusted_proxies: Vec<IpAddr>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::header::optional::<String>("x-forwarded-for")
+        .or(warp::any().map(|| None))
+        .unify()
+        .and(warp::addr::remote())
+        .map(move |forwarded: Option<String>, remote: Option<SocketAddr>| {
+            resolve_client_ip(
+                forwarded.as_deref(),
+                remote.map(|addr| addr.ip()),
+                &trusted_proxies,
+            )
+        })
+}
+
+/// Every proxy appends the address it got the request from, so the client
+/// is the last entry which isn't a trusted proxy.
+pub fn resolve_client_ip(
+    forwarded: Option<&str>,
+    remote: Option<IpAddr>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let remote = remote?;
+    if !trusted_proxies.contains(&remote) {
+        return Some(remote);
+    }
+
+    let mut client = remote;
+    for entry in forwarded.unwrap_or("").rsplit(',') {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
+
+/// Builds the subject a request is counted against, requests without
+/// claims are counted by ip.
+pub fn rate_limit_subject(
+    key: RateLimitKey,
+    claims: Option<&Claims>,
+    ip: Option<IpAddr>,
+) -> String {
+    let ip = || {
+        format!(
+            "ip: {}",
+            ip.map(|ip| ip.to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        )
+    };
+
+    match (key, claims) {
+        (RateLimitKey::User, Some(claims)) => format!("user: {}", claims.sub),
+        (RateLimitKey::Role, Some(claims)) => format!("role: {}", claims.role),
+        _ => ip(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_build_subject_from_claims() {
+        let claims = Claims::new("boris".to_string(), 0, 1, "jti".to_string());
+        let ip = Some("127.0.0.1".parse().unwrap());
+
+        assert_eq!(
+            rate_limit_subject(RateLimitKey::User, Some(&claims), ip),
+            "user: boris"
+        );
+        assert_eq!(
+            rate_limit_subject(RateLimitKey::Role, Some(&claims), ip),
+            "role: 1"
+        );
+        assert_eq!(
+            rate_limit_subject(RateLimitKey::Ip, Some(&claims), ip),
+            "ip: 127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn it_can_only_trust_forwarded_for_of_trusted_proxies() {
+        let proxy = "10.0.0.1".parse().unwrap();
+        let client = "203.0.113.7".parse().unwrap();
+        let forged = Some("198.51.100.1, 203.0.113.7");
+
+        assert_eq!(resolve_client_ip(forged, Some(client), &[proxy]), Some(client));
+        assert_eq!(resolve_client_ip(forged, Some(proxy), &[proxy]), Some(client));
+        assert_eq!(resolve_client_ip(None, Some(proxy), &[proxy]), Some(proxy));
+        assert_eq!(resolve_client_ip(Some("junk"), Some(proxy), &[proxy]), Some(proxy));
+    }
+
+    #[test]
+    fn it_falls_back_to_ip_without_claims() {
+        assert_eq!(
+            rate_limit_subject(RateLimitKey::User, None, None),
+            "ip: unknown"
+        );
+    }
+}
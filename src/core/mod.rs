@@ -1,6 +1,11 @@
+pub mod args;
+pub mod audit;
 pub mod config;
+pub mod cookie;
+pub mod cors;
 pub mod environment;
 pub mod error;
 pub mod middlewares;
+pub mod notifier;
 pub mod recover;
 pub mod util;
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::user::json::user::SimpleUser;
+use crate::AppResult;
+
+/// Delivers messages to users, e.g. by mail in production.
+#[async_trait]
+pub trait Notifier {
+    /// The reset token is a credential, it must only reach the user.
+    async fn send_password_reset(
+        &self,
+        user: &SimpleUser,
+        token: &str,
+        expires_in: usize,
+    ) -> AppResult<()>;
+}
+
+/// Appends the messages to a file, or logs them when there is no file.
+/// Only meant for local use.
+pub struct LocalNotifier {
+    path: Option<PathBuf>,
+}
+
+impl LocalNotifier {
+    pub fn new(path: Option<String>) -> Self {
+        Self {
+            path: path.map(PathBuf::from),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for LocalNotifier {
+    async fn send_password_reset(
+        &self,
+        user: &SimpleUser,
+        token: &str,
+        expires_in: usize,
+    ) -> AppResult<()> {
+        let message = format!(
+            "password reset of {} ({}): token {} expires in {} seconds.\n",
+            user.name, user.id, token, expires_in
+        );
+
+        match &self.path {
+            Some(path) => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(message.as_bytes()).await?;
+            }
+            None => info!(target: "notifier", "{}", message.trim_end()),
+        }
+
+        Ok(())
+    }
+}
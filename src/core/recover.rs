@@ -2,7 +2,7 @@ use std::convert::Infallible;
 use std::error::Error;
 
 use tracing::log::error;
-use warp::http::StatusCode;
+use warp::http::{HeaderValue, StatusCode};
 use warp::Reply;
 
 use crate::core::error::{AppError, ErrorResponse};
@@ -19,6 +19,18 @@ pub async fn rejection_handler(err: warp::Rejection) -> Result<impl Reply, Infal
     } else if let Some(AppError::AuthorizeFailed) = err.find() {
         code = StatusCode::UNAUTHORIZED;
         message = "un-authorized.";
+    } else if let Some(AppError::TokenNotExist) = err.find() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "token not exist.";
+    } else if let Some(AppError::TokenIsExpired) = err.find() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "token is expired.";
+    } else if let Some(AppError::CsrfTokenMismatch) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = "csrf token mismatch.";
+    } else if let Some(AppError::Forbidden) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = "forbidden.";
     } else if let Some(AppError::DatabaseError) = err.find() {
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "database error.";
@@ -28,6 +40,48 @@ pub async fn rejection_handler(err: warp::Rejection) -> Result<impl Reply, Infal
     } else if let Some(AppError::UserNotExist) = err.find() {
         code = StatusCode::NOT_FOUND;
         message = "user not exist.";
+    } else if let Some(AppError::UserAlreadyExist) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "user already exist.";
+    } else if let Some(AppError::UserDisabled) = err.find() {
+        code = StatusCode::FORBIDDEN;
+        message = "user disabled.";
+    } else if let Some(AppError::ValidationFailed(_)) = err.find() {
+        code = StatusCode::UNPROCESSABLE_ENTITY;
+        message = "validation failed.";
+    } else if let Some(AppError::ProviderNotExist) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "provider not exist.";
+    } else if let Some(AppError::ApiKeyNotExist) = err.find() {
+        code = StatusCode::NOT_FOUND;
+        message = "api key not exist.";
+    } else if let Some(AppError::InvalidMfaCode) = err.find() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "invalid mfa code.";
+    } else if let Some(AppError::MfaNotEnrolled) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "mfa not enrolled.";
+    } else if let Some(AppError::MfaAlreadyEnabled) = err.find() {
+        code = StatusCode::CONFLICT;
+        message = "mfa already enabled.";
+    } else if let Some(AppError::UpstreamUnavailable) = err.find() {
+        code = StatusCode::SERVICE_UNAVAILABLE;
+        message = "upstream unavailable.";
+    } else if let Some(AppError::UpstreamRequestFailed) = err.find() {
+        code = StatusCode::BAD_GATEWAY;
+        message = "upstream request failed.";
+    } else if let Some(AppError::CircuitOpen) = err.find() {
+        code = StatusCode::SERVICE_UNAVAILABLE;
+        message = "circuit open.";
+    } else if let Some(AppError::UpstreamTimeout) = err.find() {
+        code = StatusCode::GATEWAY_TIMEOUT;
+        message = "upstream timeout.";
+    } else if let Some(AppError::RateLimited { .. }) = err.find() {
+        code = StatusCode::TOO_MANY_REQUESTS;
+        message = "too many requests.";
+    } else if let Some(AppError::LoginLocked { .. }) = err.find() {
+        code = StatusCode::TOO_MANY_REQUESTS;
+        message = "too many login attempts.";
     } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
         code = StatusCode::BAD_REQUEST;
         message = match e.source() {
@@ -45,9 +99,27 @@ pub async fn rejection_handler(err: warp::Rejection) -> Result<impl Reply, Infal
         message = "unhandled rejection.";
     }
 
-    let response: ErrorResponse = (code.as_u16(), message).into();
+    let mut response: ErrorResponse = (code.as_u16(), message).into();
+    if let Some(AppError::ValidationFailed(fields)) = err.find() {
+        response.fields = fields.clone();
+    }
 
     let json = warp::reply::json(&response);
+    let mut response = warp::reply::with_status(json, code).into_response();
+
+    if let Some(AppError::RateLimited { limit, retry_after }) = err.find() {
+        let headers = response.headers_mut();
+        headers.insert("retry-after", HeaderValue::from(*retry_after));
+        headers.insert("x-ratelimit-limit", HeaderValue::from(*limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(0));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(*retry_after));
+    }
+
+    if let Some(AppError::LoginLocked { retry_after }) = err.find() {
+        response
+            .headers_mut()
+            .insert("retry-after", HeaderValue::from(*retry_after));
+    }
 
-    Ok(warp::reply::with_status(json, code))
+    Ok(response)
 }
@@ -1,7 +1,142 @@
+use rand::RngCore;
+
 use crate::Config;
 
+const SALT_LENGTH: usize = 16;
+// Hashes keyed with the pepper are stored with this prefix, so enabling the
+// pepper doesn't break the hashes made before.
+const PEPPER_PREFIX: &str = "pepper";
+
+fn argon2_config(config: &Config) -> argon2::Config {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: config.argon2_memory_kib,
+        time_cost: config.argon2_iterations,
+        lanes: config.argon2_parallelism,
+        ..argon2::Config::default()
+    }
+}
+
+/// Hashes the password with argon2id and a random salt, keyed with the
+/// pepper when there is one.
 pub fn hash_password(password: &str, config: &Config) -> Option<String> {
-    let c = argon2::Config::default();
-    let hash = argon2::hash_encoded(password.as_bytes(), config.secret_key.as_bytes(), &c);
-    hash.ok()
+    let mut salt = [0u8; SALT_LENGTH];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    let mut argon2_config = argon2_config(config);
+    match &config.password_pepper {
+        Some(pepper) => {
+            argon2_config.secret = pepper.as_bytes();
+            argon2::hash_encoded(password.as_bytes(), &salt, &argon2_config)
+                .map(|hash| format!("{}{}", PEPPER_PREFIX, hash))
+                .ok()
+        }
+        None => argon2::hash_encoded(password.as_bytes(), &salt, &argon2_config).ok(),
+    }
+}
+
+/// Verifies the password with any hash made by `hash_password`, including
+/// the hashes made with old parameters.
+pub fn verify_password(hash: &str, password: &str, config: &Config) -> bool {
+    let verify = match hash.strip_prefix(PEPPER_PREFIX) {
+        Some(hash) => match &config.password_pepper {
+            Some(pepper) => {
+                argon2::verify_encoded_ext(hash, password.as_bytes(), pepper.as_bytes(), &[])
+            }
+            None => return false,
+        },
+        None => argon2::verify_encoded(hash, password.as_bytes()),
+    };
+
+    verify.unwrap_or(false)
+}
+
+/// A hash made with other parameters, or before the pepper was enabled,
+/// is replaced on the next successful login.
+pub fn needs_rehash(hash: &str, config: &Config) -> bool {
+    let (peppered, hash) = match hash.strip_prefix(PEPPER_PREFIX) {
+        Some(hash) => (true, hash),
+        None => (false, hash),
+    };
+    if peppered != config.password_pepper.is_some() {
+        return true;
+    }
+
+    // $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+    let parts: Vec<&str> = hash.split('$').collect();
+    let expected = [
+        "argon2id".to_string(),
+        "v=19".to_string(),
+        format!(
+            "m={},t={},p={}",
+            config.argon2_memory_kib, config.argon2_iterations, config.argon2_parallelism
+        ),
+    ];
+
+    parts.len() != 6 || parts[1..4] != expected[..]
+}
+
+/// Whether `hash` was made by `hash_password` or an older version of it,
+/// rather than being a marker of a user without a password.
+pub fn is_password_hash(hash: &str) -> bool {
+    hash.strip_prefix(PEPPER_PREFIX)
+        .unwrap_or(hash)
+        .starts_with("$argon2")
+}
+
+/// Compares secrets in a time which doesn't depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_salt_every_hash() {
+        let config = Config::new().unwrap();
+        let first = hash_password("Correct1", &config).unwrap();
+        let second = hash_password("Correct1", &config).unwrap();
+
+        assert_ne!(first, second);
+        assert!(verify_password(first.as_str(), "Correct1", &config));
+        assert!(!verify_password(first.as_str(), "Wrong1", &config));
+        assert!(!needs_rehash(first.as_str(), &config));
+    }
+
+    #[test]
+    fn it_can_verify_and_upgrade_old_hash() {
+        let mut config = Config::new().unwrap();
+        config.password_pepper = None;
+        // Made by the first version, the secret key was the salt of all users.
+        let old = argon2::hash_encoded(
+            b"123",
+            config.secret_key.as_bytes(),
+            &argon2::Config::default(),
+        )
+        .unwrap();
+
+        assert!(verify_password(old.as_str(), "123", &config));
+        assert!(needs_rehash(old.as_str(), &config));
+    }
+
+    #[test]
+    fn it_can_key_hash_with_pepper() {
+        let mut config = Config::new().unwrap();
+        config.password_pepper = None;
+        let unpeppered = hash_password("Correct1", &config).unwrap();
+
+        config.password_pepper = Some("pepper".to_string());
+        let peppered = hash_password("Correct1", &config).unwrap();
+
+        assert!(verify_password(unpeppered.as_str(), "Correct1", &config));
+        assert!(needs_rehash(unpeppered.as_str(), &config));
+        assert!(verify_password(peppered.as_str(), "Correct1", &config));
+        assert!(!needs_rehash(peppered.as_str(), &config));
+
+        config.password_pepper = Some("other".to_string());
+        assert!(!verify_password(peppered.as_str(), "Correct1", &config));
+    }
 }
@@ -4,19 +4,19 @@ use std::sync::Arc;
 
 use r2d2_redis::{r2d2, RedisConnectionManager};
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
-use sqlx::Postgres;
 use warp::Filter;
 
-use common::configs::postgres_config::PostgresConfig;
-use common::configs::redis_config::RedisConfig;
-use common::utils::tools::{create_database_connection, create_redis_connection};
-
+use crate::api_key::repo::PostgresApiKeyRepository;
 use crate::auth::repo::RedisAuthRepository;
+use crate::core::args::{Args, USAGE};
 use crate::core::config::Config;
 use crate::core::environment::Environment;
+use crate::core::middlewares::cors::{self, Cors};
+use crate::core::middlewares::rate_limit::RedisRateLimiter;
 use crate::core::recover::rejection_handler;
 use crate::user::repo::PostgresUserRepository;
 
+mod api_key;
 mod auth;
 mod core;
 mod proxy;
@@ -27,37 +27,90 @@ type WebResult<T> = std::result::Result<T, warp::reject::Rejection>;
 
 #[tokio::main]
 async fn main() {
-    dotenv::from_path("env/dev.env");
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2);
+    });
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let config = Config::load(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if args.check_config {
+        println!("the config is valid.");
+        return;
+    }
 
     tracing_subscriber::fmt()
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
         .init();
 
-    let postgres = PostgresConfig::new();
-    let database_connection_pool = create_database_connection(postgres).await
-        .expect("Can create a database connection pool.");
+    let postgres = PgConnectOptions::new()
+        .host(config.postgres_host.as_str())
+        .port(config.postgres_port)
+        .username(config.postgres_username.as_str())
+        .password(config.postgres_password.as_str())
+        .database(config.postgres_database.as_str());
+    let database_connection_pool = Arc::new(
+        PgPoolOptions::new()
+            .max_connections(config.postgres_max_connections)
+            .connect_with(postgres)
+            .await
+            .expect("Can't create a database connection pool."),
+    );
 
-    let redis = RedisConfig::new();
-    let redis_connection = create_redis_connection(redis).await
-        .expect("Can create a redis connection.");
+    let redis = RedisConnectionManager::new(format!(
+        "redis://{}:{}@{}:{}",
+        config.redis_username.as_deref().unwrap_or(""),
+        config.redis_password,
+        config.redis_host,
+        config.redis_port
+    ))
+    .expect("Can't parse the redis url.");
+    let redis_connection_pool = r2d2::Pool::builder()
+        .max_size(config.redis_max_connections)
+        .build(redis)
+        .expect("Can't create a redis connection pool.");
 
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["Access-Control-Allow-Origin", "Content-Type"])
-        .allow_credentials(true)
-        .expose_headers(vec!["set-cookie"])
-        .allow_methods(vec!["GET", "POST", "DELETE", "PUT", "PATCH"]);
+    let env = Environment::new(
+        config.clone(),
+        Arc::new(RedisAuthRepository::new(redis_connection_pool.clone())),
+        Arc::new(PostgresUserRepository::new(database_connection_pool.clone())),
+        Arc::new(PostgresApiKeyRepository::new(database_connection_pool.clone())),
+        Arc::new(RedisRateLimiter::new(redis_connection_pool)),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
 
+    let auth_routes = auth::route::routes(env.clone());
+    let user_routes = user::route::routes(env.clone());
+    let api_key_routes = api_key::route::routes(env.clone());
     let proxy_routes = proxy::route::routes(env.clone());
+    proxy::health::spawn_health_checker(env.route_table.clone());
 
+    let cors = Arc::new(Cors::new(&env.config.cors));
     let routes = auth_routes
         .or(user_routes)
+        .or(api_key_routes)
         .or(proxy_routes)
-        .with(cors)
-        .with(warp::trace::request())
         .recover(rejection_handler);
+    let routes = cors::preflight(cors.clone())
+        .or(cors::with_cors(cors, routes))
+        .with(warp::trace::request());
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    let server = warp::serve(routes);
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => {
+            server.tls().cert_path(cert).key_path(key).run(config.listen_addr).await
+        }
+        _ => server.run(config.listen_addr).await,
+    }
 
     database_connection_pool.close().await;
 }
@@ -0,0 +1,365 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use crate::proxy::breaker::CircuitBreaker;
+use crate::proxy::json::config::{HealthCheckConfig, ServiceConfig, Strategy};
+use crate::proxy::json::status::{InstanceStatus, PoolStatus};
+
+const VIRTUAL_NODES: u32 = 100;
+
+#[derive(Debug)]
+pub struct Instance {
+    pub url: String,
+    pub weight: u32,
+    outstanding: AtomicUsize,
+    healthy: AtomicBool,
+    failures: AtomicU32,
+    successes: AtomicU32,
+}
+
+impl Instance {
+    pub fn new(url: &str, weight: u32) -> Self {
+        Self {
+            url: url.to_string(),
+            weight: weight.max(1),
+            outstanding: AtomicUsize::new(0),
+            healthy: AtomicBool::new(true),
+            failures: AtomicU32::new(0),
+            successes: AtomicU32::new(0),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    /// Marks a request as in flight until the returned guard is dropped.
+    pub fn acquire(self: &Arc<Self>) -> InstanceGuard {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        InstanceGuard(self.clone())
+    }
+}
+
+pub struct InstanceGuard(Arc<Instance>);
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        self.0.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+pub struct UpstreamPool {
+    pub name: String,
+    pub strategy: Strategy,
+    pub health_check: HealthCheckConfig,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    instances: Vec<Arc<Instance>>,
+    counter: AtomicUsize,
+    ring: Vec<(u64, usize)>,
+}
+
+impl UpstreamPool {
+    pub fn new(config: &ServiceConfig) -> Result<Self, String> {
+        if config.instances.is_empty() {
+            return Err(format!("service {} has no instances.", config.name));
+        }
+        // Round robin would still pick an instance of weight 0.
+        if config.instances.iter().any(|i| i.weight == 0) {
+            return Err(format!("service {} has an instance of weight 0.", config.name));
+        }
+
+        let instances = config
+            .instances
+            .iter()
+            .map(|i| Arc::new(Instance::new(i.url.as_str(), i.weight)))
+            .collect::<Vec<_>>();
+
+        let mut ring = instances
+            .iter()
+            .enumerate()
+            .flat_map(|(index, instance)| {
+                (0..VIRTUAL_NODES * instance.weight)
+                    .map(move |node| (hash(&format!("{}#{}", instance.url, node)), index))
+            })
+            .collect::<Vec<_>>();
+        ring.sort_unstable();
+
+        Ok(Self {
+            name: config.name.clone(),
+            strategy: config.strategy,
+            health_check: config.health_check.clone(),
+            circuit_breaker: config
+                .circuit_breaker
+                .clone()
+                .map(|c| CircuitBreaker::new(config.name.as_str(), c)),
+            instances,
+            counter: AtomicUsize::new(0),
+            ring,
+        })
+    }
+
+    pub fn instances(&self) -> &[Arc<Instance>] {
+        &self.instances
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            name: self.name.clone(),
+            strategy: self.strategy,
+            circuit: self.circuit_breaker.as_ref().map(|b| b.state()),
+            instances: self
+                .instances
+                .iter()
+                .map(|i| InstanceStatus {
+                    url: i.url.clone(),
+                    weight: i.weight,
+                    healthy: i.is_healthy(),
+                    outstanding: i.outstanding(),
+                    consecutive_failures: i.failures.load(Ordering::Relaxed),
+                })
+                .collect(),
+        }
+    }
+
+    /// Records a successful request or probe, an ejected instance is put
+    /// back into rotation after `healthy_threshold` successes in a row.
+    pub fn report_success(&self, instance: &Instance) {
+        instance.failures.store(0, Ordering::Relaxed);
+
+        if instance.is_healthy() {
+            return;
+        }
+
+        let successes = instance.successes.fetch_add(1, Ordering::Relaxed) + 1;
+        if successes >= self.health_check.healthy_threshold {
+            self.reinstate(instance);
+        }
+    }
+
+    /// Records a failed request or probe, the instance is ejected from
+    /// rotation after `unhealthy_threshold` failures in a row.
+    pub fn report_failure(&self, instance: &Instance) {
+        instance.successes.store(0, Ordering::Relaxed);
+
+        let failures = instance.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.health_check.unhealthy_threshold
+            && instance.healthy.swap(false, Ordering::Relaxed)
+        {
+            warn!(
+                "eject the instance {} of {} after {} failures.",
+                instance.url, self.name, failures
+            );
+        }
+    }
+
+    pub fn reinstate(&self, instance: &Instance) {
+        instance.failures.store(0, Ordering::Relaxed);
+        instance.successes.store(0, Ordering::Relaxed);
+
+        if !instance.healthy.swap(true, Ordering::Relaxed) {
+            info!("reinstate the instance {} of {}.", instance.url, self.name);
+        }
+    }
+
+    /// Picks a healthy instance for the next request.
+    ///
+    /// `key` is only used by the consistent hashing strategy, requests
+    /// without a key fall back to round-robin.
+    pub fn select(&self, key: Option<&str>) -> Option<Arc<Instance>> {
+        let index = match (self.strategy, key) {
+            (Strategy::RoundRobin, _) | (Strategy::ConsistentHash, None) => self.round_robin(),
+            (Strategy::LeastOutstanding, _) => self.least_outstanding(),
+            (Strategy::Weighted, _) => self.weighted(),
+            (Strategy::ConsistentHash, Some(key)) => self.consistent_hash(key),
+        };
+
+        index.map(|i| self.instances[i].clone())
+    }
+
+    fn next(&self) -> usize {
+        self.counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn round_robin(&self) -> Option<usize> {
+        let offset = self.next();
+        let len = self.instances.len();
+
+        (0..len)
+            .map(|i| (i + offset) % len)
+            .find(|&i| self.instances[i].is_healthy())
+    }
+
+    fn least_outstanding(&self) -> Option<usize> {
+        let offset = self.next();
+        let len = self.instances.len();
+
+        // Start from a rotating offset so ties are spread across instances.
+        (0..len)
+            .map(|i| (i + offset) % len)
+            .filter(|&i| self.instances[i].is_healthy())
+            .min_by_key(|&i| self.instances[i].outstanding())
+    }
+
+    fn weighted(&self) -> Option<usize> {
+        let total = self
+            .instances
+            .iter()
+            .filter(|i| i.is_healthy())
+            .map(|i| i.weight as usize)
+            .sum::<usize>();
+        if total == 0 {
+            return None;
+        }
+
+        let mut point = self.next() % total;
+        for (index, instance) in self.instances.iter().enumerate() {
+            if !instance.is_healthy() {
+                continue;
+            }
+            if point < instance.weight as usize {
+                return Some(index);
+            }
+            point -= instance.weight as usize;
+        }
+        None
+    }
+
+    fn consistent_hash(&self, key: &str) -> Option<usize> {
+        if self.ring.is_empty() {
+            return None;
+        }
+
+        let h = hash(&key);
+        let len = self.ring.len();
+        let position = self.ring.partition_point(|(node, _)| *node < h);
+
+        // Walk the ring clockwise until a healthy instance is found, so only
+        // the users of an ejected instance are moved.
+        (0..len)
+            .map(|i| self.ring[(position + i) % len].1)
+            .find(|&i| self.instances[i].is_healthy())
+    }
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proxy::json::config::InstanceConfig;
+
+    use super::*;
+
+    fn pool(strategy: Strategy, weights: &[u32]) -> UpstreamPool {
+        UpstreamPool::new(&ServiceConfig {
+            name: "customers".to_string(),
+            strategy,
+            instances: weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| InstanceConfig {
+                    url: format!("http://127.0.0.1:{}", 3031 + i),
+                    weight: *w,
+                })
+                .collect(),
+            health_check: HealthCheckConfig::default(),
+            circuit_breaker: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn it_can_select_by_round_robin() {
+        let pool = pool(Strategy::RoundRobin, &[1, 1, 1]);
+
+        let urls = (0..3)
+            .map(|_| pool.select(None).unwrap().url.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            urls,
+            vec![
+                "http://127.0.0.1:3031",
+                "http://127.0.0.1:3032",
+                "http://127.0.0.1:3033"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_can_select_the_least_outstanding_instance() {
+        let pool = pool(Strategy::LeastOutstanding, &[1, 1]);
+
+        let busy = pool.select(None).unwrap();
+        let _guard = busy.acquire();
+
+        for _ in 0..4 {
+            assert_ne!(pool.select(None).unwrap().url, busy.url);
+        }
+    }
+
+    #[test]
+    fn it_can_select_by_weight() {
+        let pool = pool(Strategy::Weighted, &[3, 1]);
+
+        let heavy = (0..8)
+            .filter(|_| pool.select(None).unwrap().url == "http://127.0.0.1:3031")
+            .count();
+
+        assert_eq!(heavy, 6);
+    }
+
+    #[test]
+    fn it_can_eject_and_reinstate_an_instance() {
+        let pool = pool(Strategy::RoundRobin, &[1, 1]);
+        let instance = pool.instances()[0].clone();
+
+        for _ in 0..3 {
+            pool.report_failure(&instance);
+        }
+        assert!(!instance.is_healthy());
+        for _ in 0..4 {
+            assert_ne!(pool.select(None).unwrap().url, instance.url);
+        }
+
+        for _ in 0..2 {
+            pool.report_success(&instance);
+        }
+        assert!(instance.is_healthy());
+    }
+
+    #[test]
+    fn it_cannot_select_when_all_instances_are_ejected() {
+        let pool = pool(Strategy::ConsistentHash, &[1]);
+        let instance = pool.instances()[0].clone();
+
+        for _ in 0..3 {
+            pool.report_failure(&instance);
+        }
+
+        assert!(pool.select(Some("boris")).is_none());
+    }
+
+    #[test]
+    fn it_can_stick_to_the_same_instance_by_key() {
+        let pool = pool(Strategy::ConsistentHash, &[1, 1, 1]);
+        let user_id = uuid::Uuid::new_v4().to_string();
+
+        let first = pool.select(Some(user_id.as_str())).unwrap();
+        for _ in 0..10 {
+            assert_eq!(pool.select(Some(user_id.as_str())).unwrap().url, first.url);
+        }
+    }
+}
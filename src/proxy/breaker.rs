@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::core::error::AppError;
+use crate::proxy::json::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    opened_at: Option<Instant>,
+    // (second, total, failures), one bucket per second of the window.
+    buckets: VecDeque<(u64, u32, u32)>,
+    probes: u32,
+    probe_successes: u32,
+}
+
+/// Stops forwarding to a service once too many calls fail or are too slow.
+///
+/// The circuit opens when the failure rate over the last `window_seconds`
+/// reaches `error_rate_threshold`, calls slower than `slow_call_ms` count as
+/// failures. After `open_seconds` a few probe calls are let through, the
+/// circuit closes again when all of them succeed.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    config: CircuitBreakerConfig,
+    created_at: Instant,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, config: CircuitBreakerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            created_at: Instant::now(),
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                opened_at: None,
+                buckets: VecDeque::new(),
+                probes: 0,
+                probe_successes: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Checks whether a call may go through. The outcome is recorded by the
+    /// permit, a permit dropped before it counts as a failure, so a probe
+    /// cancelled by a timeout or a disconnect frees its half open slot.
+    pub fn try_acquire(&self) -> Result<Permit<'_>, AppError> {
+        self.acquire()?;
+
+        Ok(Permit {
+            breaker: self,
+            acquired_at: Instant::now(),
+            recorded: false,
+        })
+    }
+
+    fn acquire(&self) -> Result<(), AppError> {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open => {
+                let elapsed = inner.opened_at.map(|t| t.elapsed()).unwrap_or_default();
+                if elapsed < Duration::from_secs(self.config.open_seconds) {
+                    return Err(AppError::CircuitOpen);
+                }
+
+                inner.state = CircuitState::HalfOpen;
+                inner.probes = 1;
+                inner.probe_successes = 0;
+                Ok(())
+            }
+            CircuitState::HalfOpen => {
+                if inner.probes >= self.config.half_open_requests {
+                    return Err(AppError::CircuitOpen);
+                }
+
+                inner.probes += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn record(&self, success: bool, elapsed: Duration) {
+        let failed = !success || elapsed >= Duration::from_millis(self.config.slow_call_ms);
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.state {
+            CircuitState::Closed => {
+                let now = self.created_at.elapsed().as_secs();
+                self.add_to_window(&mut inner, now, failed);
+
+                let (total, failures) = inner
+                    .buckets
+                    .iter()
+                    .fold((0, 0), |(t, f), (_, total, failures)| (t + total, f + failures));
+
+                if total >= self.config.minimum_requests
+                    && failures as f64 / total as f64 >= self.config.error_rate_threshold
+                {
+                    warn!(
+                        "open the circuit of {}, {} of {} calls failed.",
+                        self.name, failures, total
+                    );
+                    self.open(&mut inner);
+                }
+            }
+            CircuitState::HalfOpen => {
+                if failed {
+                    warn!("reopen the circuit of {}, the probe failed.", self.name);
+                    self.open(&mut inner);
+                    return;
+                }
+
+                inner.probe_successes += 1;
+                if inner.probe_successes >= self.config.half_open_requests {
+                    inner.state = CircuitState::Closed;
+                    inner.opened_at = None;
+                    inner.buckets.clear();
+                }
+            }
+            CircuitState::Open => {}
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) {
+        inner.state = CircuitState::Open;
+        inner.opened_at = Some(Instant::now());
+        inner.buckets.clear();
+    }
+
+    fn add_to_window(&self, inner: &mut Inner, now: u64, failed: bool) {
+        while let Some((second, _, _)) = inner.buckets.front() {
+            if now - second < self.config.window_seconds {
+                break;
+            }
+            inner.buckets.pop_front();
+        }
+
+        match inner.buckets.back_mut() {
+            Some((second, total, failures)) if *second == now => {
+                *total += 1;
+                *failures += failed as u32;
+            }
+            _ => inner.buckets.push_back((now, 1, failed as u32)),
+        }
+    }
+}
+
+/// A call let through by `CircuitBreaker::try_acquire`.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    acquired_at: Instant,
+    recorded: bool,
+}
+
+impl Permit<'_> {
+    pub fn record(mut self, success: bool) {
+        self.recorded = true;
+        self.breaker.record(success, self.acquired_at.elapsed());
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.record(false, self.acquired_at.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn breaker(open_seconds: u64) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "customers",
+            CircuitBreakerConfig {
+                error_rate_threshold: 0.5,
+                slow_call_ms: 1000,
+                minimum_requests: 4,
+                window_seconds: 10,
+                open_seconds,
+                half_open_requests: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn it_can_open_after_too_many_failures() {
+        let breaker = breaker(30);
+
+        breaker.record(true, Duration::from_millis(10));
+        breaker.record(true, Duration::from_millis(10));
+        breaker.record(false, Duration::from_millis(10));
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record(true, Duration::from_millis(2000));
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(breaker.try_acquire(), Err(AppError::CircuitOpen)));
+    }
+
+    #[test]
+    fn it_can_close_after_a_successful_probe() {
+        let breaker = breaker(0);
+
+        for _ in 0..4 {
+            breaker.record(false, Duration::from_millis(10));
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let permit = breaker.try_acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(matches!(breaker.try_acquire(), Err(AppError::CircuitOpen)));
+
+        permit.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn it_can_reopen_after_a_failed_probe() {
+        let breaker = breaker(0);
+
+        for _ in 0..4 {
+            breaker.record(false, Duration::from_millis(10));
+        }
+
+        breaker.try_acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn it_can_free_the_probe_of_a_dropped_call() {
+        let breaker = breaker(0);
+
+        for _ in 0..4 {
+            breaker.record(false, Duration::from_millis(10));
+        }
+
+        let probe = breaker.try_acquire().unwrap();
+        drop(probe);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        breaker.try_acquire().unwrap().record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
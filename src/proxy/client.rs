@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use reqwest::redirect::Policy;
+use warp::http::{HeaderMap, HeaderValue, Method, Response};
+use warp::hyper::body::Bytes;
+
+use crate::core::error::AppError;
+use crate::proxy::json::config::TimeoutConfig;
+
+const HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Http client used to forward requests to the instances of one route.
+#[derive(Debug, Clone)]
+pub struct UpstreamClient {
+    client: reqwest::Client,
+    read_timeout: Duration,
+}
+
+impl UpstreamClient {
+    pub fn new(config: &TimeoutConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .connect_timeout(Duration::from_millis(config.connect_ms))
+            .build()
+            .expect("Can't build the upstream client.");
+
+        Self {
+            client,
+            read_timeout: Duration::from_millis(config.read_ms),
+        }
+    }
+
+    pub async fn send(
+        &self,
+        url: &str,
+        method: Method,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Result<Response<Bytes>, AppError> {
+        let request = self
+            .client
+            .request(method, url)
+            .headers(remove_hop_headers(headers))
+            .body(body);
+
+        let response = tokio::time::timeout(self.read_timeout, request.send())
+            .await
+            .map_err(|_| AppError::UpstreamTimeout)?
+            .map_err(to_app_error)?;
+
+        let mut builder = Response::builder().status(response.status());
+        for (k, v) in remove_hop_headers(response.headers()).iter() {
+            builder = builder.header(k, v);
+        }
+
+        let body = tokio::time::timeout(self.read_timeout, response.bytes())
+            .await
+            .map_err(|_| AppError::UpstreamTimeout)?
+            .map_err(to_app_error)?;
+
+        builder
+            .body(body)
+            .map_err(|_| AppError::UpstreamRequestFailed)
+    }
+}
+
+fn to_app_error(e: reqwest::Error) -> AppError {
+    if e.is_timeout() {
+        AppError::UpstreamTimeout
+    } else {
+        AppError::UpstreamRequestFailed
+    }
+}
+
+fn remove_hop_headers(headers: &HeaderMap<HeaderValue>) -> HeaderMap<HeaderValue> {
+    headers
+        .iter()
+        .filter(|(k, _)| !HOP_HEADERS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
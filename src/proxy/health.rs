@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::proxy::balancer::{Instance, UpstreamPool};
+use crate::proxy::table::RouteTable;
+
+/// Spawns one background task per upstream pool which keeps the health
+/// state of its instances up to date.
+pub fn spawn_health_checker(table: Arc<RouteTable>) {
+    let client = reqwest::Client::new();
+
+    for pool in table.pools() {
+        tokio::spawn(check_pool(pool.clone(), client.clone()));
+    }
+}
+
+async fn check_pool(pool: Arc<UpstreamPool>, client: reqwest::Client) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(pool.health_check.interval_seconds.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        match &pool.health_check.path {
+            Some(path) => {
+                for instance in pool.instances() {
+                    if probe(&client, instance, path, pool.health_check.timeout_seconds).await {
+                        pool.report_success(instance);
+                    } else {
+                        pool.report_failure(instance);
+                    }
+                }
+            }
+            None => {
+                // Without an endpoint to probe, give ejected instances another
+                // chance and let the proxy path eject them again if needed.
+                for instance in pool.instances().iter().filter(|i| !i.is_healthy()) {
+                    pool.reinstate(instance);
+                }
+            }
+        }
+    }
+}
+
+async fn probe(client: &reqwest::Client, instance: &Instance, path: &str, timeout: u64) -> bool {
+    let url = format!(
+        "{}/{}",
+        instance.url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+
+    let response = client
+        .get(url.as_str())
+        .timeout(Duration::from_secs(timeout))
+        .send()
+        .await;
+
+    debug!("health check {}: {:?}", url, response.as_ref().map(|r| r.status()));
+
+    response.map(|r| r.status().is_success()).unwrap_or(false)
+}
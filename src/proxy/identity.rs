@@ -0,0 +1,179 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use warp::http::header::{AUTHORIZATION, COOKIE};
+use warp::http::{HeaderMap, HeaderValue};
+
+use crate::auth::json::claims::Claims;
+use crate::core::cookie::CookieConfig;
+use crate::core::middlewares::authorization::API_KEY_HEADER;
+
+pub const USER_ID_HEADER: &str = "x-user-id";
+pub const USER_ROLE_HEADER: &str = "x-user-role";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TIMESTAMP_HEADER: &str = "x-gateway-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-gateway-signature";
+
+const IDENTITY_HEADERS: [&str; 5] = [
+    USER_ID_HEADER,
+    USER_ROLE_HEADER,
+    REQUEST_ID_HEADER,
+    TIMESTAMP_HEADER,
+    SIGNATURE_HEADER,
+];
+
+/// Replaces any identity headers sent by the client with the identity the
+/// gateway authenticated, and returns the generated request id.
+///
+/// With a `signing_key` the headers are signed with HMAC-SHA256 over
+/// `user_id\nrole\nrequest_id\ntimestamp`, so a backend can trust them
+/// without validating the session again.
+pub fn forward_identity(
+    headers: &mut HeaderMap,
+    claims: Option<&Claims>,
+    signing_key: Option<&str>,
+) -> String {
+    for name in IDENTITY_HEADERS {
+        headers.remove(name);
+    }
+
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let user_id = claims.map(|c| c.sub.clone()).unwrap_or_default();
+    let role = claims.map(|c| c.role.to_string()).unwrap_or_default();
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+
+    if claims.is_some() {
+        insert(headers, USER_ID_HEADER, user_id.as_str());
+        insert(headers, USER_ROLE_HEADER, role.as_str());
+    }
+    insert(headers, REQUEST_ID_HEADER, request_id.as_str());
+
+    if let Some(key) = signing_key {
+        let signature = sign(
+            key,
+            user_id.as_str(),
+            role.as_str(),
+            request_id.as_str(),
+            timestamp.as_str(),
+        );
+        insert(headers, TIMESTAMP_HEADER, timestamp.as_str());
+        insert(headers, SIGNATURE_HEADER, signature.as_str());
+    }
+
+    request_id
+}
+
+/// Removes the credentials of the gateway session, an upstream or its logs
+/// could replay them. Other cookies are kept.
+pub fn strip_credentials(headers: &mut HeaderMap, cookie: &CookieConfig) {
+    headers.remove(AUTHORIZATION);
+    headers.remove(API_KEY_HEADER);
+
+    let gateway_cookies = [
+        cookie.token_name(),
+        cookie.refresh_token_name(),
+        cookie.csrf_token_name(),
+    ];
+    let kept = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or_default().trim();
+            !pair.is_empty() && !gateway_cookies.contains(&name)
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    headers.remove(COOKIE);
+    if !kept.is_empty() {
+        insert(headers, "cookie", kept.as_str());
+    }
+}
+
+pub fn sign(key: &str, user_id: &str, role: &str, request_id: &str, timestamp: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC can take key of any size.");
+    mac.update(format!("{}\n{}\n{}\n{}", user_id, role, request_id, timestamp).as_bytes());
+    base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD)
+}
+
+fn insert(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    #[test]
+    fn it_can_replace_client_supplied_identity() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_ID_HEADER, HeaderValue::from_static("admin"));
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_static("forged"));
+
+        let claims = Claims::new("boris".to_string(), 0, 1, "jti".to_string());
+        let request_id = forward_identity(&mut headers, Some(&claims), None);
+
+        assert_eq!(value(&headers, USER_ID_HEADER), Some("boris"));
+        assert_eq!(value(&headers, USER_ROLE_HEADER), Some("1"));
+        assert_eq!(value(&headers, REQUEST_ID_HEADER), Some(request_id.as_str()));
+        assert!(headers.get(SIGNATURE_HEADER).is_none());
+    }
+
+    #[test]
+    fn it_cannot_forward_identity_without_claims() {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_ID_HEADER, HeaderValue::from_static("admin"));
+
+        forward_identity(&mut headers, None, None);
+
+        assert!(headers.get(USER_ID_HEADER).is_none());
+        assert!(headers.get(REQUEST_ID_HEADER).is_some());
+    }
+
+    #[test]
+    fn it_can_sign_identity() {
+        let mut headers = HeaderMap::new();
+        let claims = Claims::new("boris".to_string(), 0, 1, "jti".to_string());
+
+        let request_id = forward_identity(&mut headers, Some(&claims), Some("secret"));
+
+        let expected = sign(
+            "secret",
+            "boris",
+            "1",
+            request_id.as_str(),
+            value(&headers, TIMESTAMP_HEADER).unwrap(),
+        );
+        assert_eq!(value(&headers, SIGNATURE_HEADER), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn it_cannot_forward_gateway_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer jwt"));
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("key"));
+        headers.append(COOKIE, HeaderValue::from_static("token=jwt; theme=dark"));
+        headers.append(COOKIE, HeaderValue::from_static("csrf_token=csrf; refresh_token=r"));
+
+        let cookie = CookieConfig {
+            same_site: crate::core::cookie::SameSite::Lax,
+            secure: false,
+            host_prefix: false,
+        };
+        strip_credentials(&mut headers, &cookie);
+
+        assert!(headers.get(AUTHORIZATION).is_none());
+        assert!(headers.get(API_KEY_HEADER).is_none());
+        assert_eq!(headers.get_all(COOKIE).iter().count(), 1);
+        assert_eq!(value(&headers, "cookie"), Some("theme=dark"));
+    }
+}
@@ -0,0 +1,352 @@
+use serde::{Deserialize, Serialize};
+use warp::http::Method;
+
+use crate::auth::role::{Permission, Role};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub services: Vec<ServiceConfig>,
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    #[serde(default)]
+    pub retry_budget: RetryBudgetConfig,
+}
+
+impl ProxyConfig {
+    pub fn from_path(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config = toml::from_str::<ProxyConfig>(content.as_str())?;
+        Ok(config)
+    }
+
+    /// Every problem of the services and routes, so they can be fixed at
+    /// once.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        for (i, service) in self.services.iter().enumerate() {
+            if self.services[..i].iter().any(|s| s.name == service.name) {
+                errors.push(format!("service {} is defined twice.", service.name));
+            }
+            if service.instances.is_empty() {
+                errors.push(format!("service {} has no instances.", service.name));
+            }
+            if service.instances.iter().any(|i| i.weight == 0) {
+                errors.push(format!("service {} has an instance of weight 0.", service.name));
+            }
+        }
+
+        for route in &self.routes {
+            if !route.prefix.starts_with('/') {
+                errors.push(format!("route {} doesn't start with /.", route.prefix));
+            }
+            if let Err(e) = route.methods() {
+                errors.push(e);
+            }
+            let timeout = &route.timeout;
+            if timeout.connect_ms == 0 || timeout.read_ms == 0 || timeout.total_ms == 0 {
+                errors.push(format!("route {} needs timeouts greater than 0.", route.prefix));
+            }
+            if route.retry.as_ref().map_or(false, |retry| retry.attempts == 0) {
+                errors.push(format!("route {} needs retry attempts greater than 0.", route.prefix));
+            }
+            // The limiter fails open, a limit it can't count is no limit.
+            if let Some(rate_limit) = &route.rate_limit {
+                if rate_limit.limit == 0 || rate_limit.window_seconds == 0 {
+                    errors.push(format!(
+                        "route {} needs a rate limit and window greater than 0.",
+                        route.prefix
+                    ));
+                }
+            }
+            if !self.services.iter().any(|s| s.name == route.service) {
+                errors.push(format!(
+                    "route {} uses the unknown service {}.",
+                    route.prefix, route.service
+                ));
+            }
+        }
+
+        let budget = &self.retry_budget;
+        if !budget.ratio.is_finite() || budget.ratio <= 0.0 || budget.window_seconds == 0 {
+            errors.push("the retry budget needs a ratio and window greater than 0.".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A named upstream service the gateway can forward requests to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub strategy: Strategy,
+    pub instances: Vec<InstanceConfig>,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstanceConfig {
+    pub url: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// How requests are spread over the instances of a service.
+///
+/// `ConsistentHash` keys on the authenticated user id, so a user keeps
+/// hitting the same instance while the pool doesn't change.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    RoundRobin,
+    LeastOutstanding,
+    Weighted,
+    ConsistentHash,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::RoundRobin
+    }
+}
+
+/// Health checking of the instances of a service.
+///
+/// Failures seen while proxying are always counted. When `path` is set the
+/// instances are also probed every `interval_seconds`, otherwise an ejected
+/// instance is put back into rotation after one interval.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthCheckConfig {
+    pub path: Option<String>,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval_seconds: default_interval_seconds(),
+            timeout_seconds: default_timeout_seconds(),
+            unhealthy_threshold: default_unhealthy_threshold(),
+            healthy_threshold: default_healthy_threshold(),
+        }
+    }
+}
+
+/// Circuit breaking of a service, see `proxy::breaker::CircuitBreaker`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitBreakerConfig {
+    #[serde(default = "default_error_rate_threshold")]
+    pub error_rate_threshold: f64,
+    #[serde(default = "default_slow_call_ms")]
+    pub slow_call_ms: u64,
+    #[serde(default = "default_minimum_requests")]
+    pub minimum_requests: u32,
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64,
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: u32,
+}
+
+/// Maps incoming requests to a service.
+///
+/// `methods` and `hosts` are optional, an empty list matches everything.
+/// `roles` and `permissions` are only checked on authenticated routes, api
+/// keys are only accepted on routes with `permissions` in their scopes.
+/// When `rewrite` is set the matched `prefix` is replaced by it before the
+/// request is forwarded, otherwise the full path is forwarded unchanged.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    pub prefix: String,
+    pub service: String,
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default = "default_authenticated")]
+    pub authenticated: bool,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    pub rewrite: Option<String>,
+    #[serde(default)]
+    pub timeout: TimeoutConfig,
+    pub retry: Option<RetryConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl RouteConfig {
+    pub fn methods(&self) -> Result<Vec<Method>, String> {
+        self.methods
+            .iter()
+            .map(|m| {
+                Method::from_bytes(m.to_uppercase().as_bytes())
+                    .map_err(|_| format!("route {} has the invalid method {:?}.", self.prefix, m))
+            })
+            .collect()
+    }
+}
+
+/// Allows `limit` requests per `window_seconds` for every user, role or ip,
+/// counted in redis so the limit is shared by all gateway replicas.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    pub key: RateLimitKey,
+    pub limit: u32,
+    pub window_seconds: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    User,
+    Role,
+    Ip,
+}
+
+/// Timeouts of a route, `read_ms` applies to every attempt while `total_ms`
+/// bounds the whole request including retries.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeoutConfig {
+    #[serde(default = "default_connect_ms")]
+    pub connect_ms: u64,
+    #[serde(default = "default_read_ms")]
+    pub read_ms: u64,
+    #[serde(default = "default_total_ms")]
+    pub total_ms: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_ms: default_connect_ms(),
+            read_ms: default_read_ms(),
+            total_ms: default_total_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryConfig {
+    pub attempts: u32,
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+/// Shared by all routes, see `proxy::retry::RetryBudget`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryBudgetConfig {
+    #[serde(default = "default_retry_ratio")]
+    pub ratio: f64,
+    #[serde(default = "default_min_retries_per_second")]
+    pub min_retries_per_second: u32,
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: default_retry_ratio(),
+            min_retries_per_second: default_min_retries_per_second(),
+            window_seconds: default_window_seconds(),
+        }
+    }
+}
+
+fn default_authenticated() -> bool {
+    true
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_interval_seconds() -> u64 {
+    10
+}
+
+fn default_timeout_seconds() -> u64 {
+    2
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
+
+fn default_healthy_threshold() -> u32 {
+    2
+}
+
+fn default_error_rate_threshold() -> f64 {
+    0.5
+}
+
+fn default_slow_call_ms() -> u64 {
+    5000
+}
+
+fn default_minimum_requests() -> u32 {
+    20
+}
+
+fn default_window_seconds() -> u64 {
+    10
+}
+
+fn default_open_seconds() -> u64 {
+    30
+}
+
+fn default_half_open_requests() -> u32 {
+    1
+}
+
+fn default_connect_ms() -> u64 {
+    1000
+}
+
+fn default_read_ms() -> u64 {
+    10000
+}
+
+fn default_total_ms() -> u64 {
+    30000
+}
+
+fn default_base_delay_ms() -> u64 {
+    50
+}
+
+fn default_max_delay_ms() -> u64 {
+    1000
+}
+
+fn default_retry_ratio() -> f64 {
+    0.2
+}
+
+fn default_min_retries_per_second() -> u32 {
+    10
+}
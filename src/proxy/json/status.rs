@@ -0,0 +1,21 @@
+use serde::Serialize;
+
+use crate::proxy::breaker::CircuitState;
+use crate::proxy::json::config::Strategy;
+
+#[derive(Debug, Serialize)]
+pub struct PoolStatus {
+    pub name: String,
+    pub strategy: Strategy,
+    pub circuit: Option<CircuitState>,
+    pub instances: Vec<InstanceStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceStatus {
+    pub url: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub outstanding: usize,
+    pub consecutive_failures: u32,
+}
@@ -1 +1,9 @@
-pub mod route;
\ No newline at end of file
+pub mod balancer;
+pub mod breaker;
+pub mod client;
+pub mod health;
+pub mod identity;
+pub mod json;
+pub mod retry;
+pub mod route;
+pub mod table;
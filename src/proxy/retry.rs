@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use warp::http::Method;
+
+use crate::proxy::json::config::{RetryBudgetConfig, RetryConfig};
+
+/// Retries of idempotent requests with an exponential backoff and full
+/// jitter, `attempts` doesn't include the first try.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            attempts: config.attempts,
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+        }
+    }
+
+    pub fn is_retryable(&self, method: &Method) -> bool {
+        matches!(
+            *method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+        )
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        let millis = exponential.as_millis() as u64;
+        if millis == 0 {
+            return exponential;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+/// Limits retries across all routes to a ratio of the recent requests, so
+/// retries can't multiply the load on a backend which is already failing.
+///
+/// On top of the ratio `min_retries_per_second` retries are always allowed,
+/// which keeps retries working when the traffic is low.
+#[derive(Debug)]
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    created_at: Instant,
+    // (second, requests, retries), one bucket per second of the window.
+    buckets: Mutex<VecDeque<(u64, u32, u32)>>,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        Self {
+            config,
+            created_at: Instant::now(),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn deposit(&self) {
+        self.update(|bucket| bucket.1 += 1);
+    }
+
+    pub fn try_withdraw(&self) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = self.prune(&mut buckets);
+
+        let (requests, retries) = buckets
+            .iter()
+            .fold((0, 0), |(req, ret), (_, requests, retries)| (req + requests, ret + retries));
+
+        let allowed = self.config.min_retries_per_second as f64 * self.config.window_seconds as f64
+            + self.config.ratio * requests as f64;
+
+        if retries as f64 + 1.0 > allowed {
+            return false;
+        }
+
+        Self::bucket(&mut buckets, now).2 += 1;
+        true
+    }
+
+    fn update<F: FnOnce(&mut (u64, u32, u32))>(&self, f: F) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = self.prune(&mut buckets);
+        f(Self::bucket(&mut buckets, now));
+    }
+
+    fn prune(&self, buckets: &mut VecDeque<(u64, u32, u32)>) -> u64 {
+        let now = self.created_at.elapsed().as_secs();
+        while let Some((second, _, _)) = buckets.front() {
+            if now - second < self.config.window_seconds {
+                break;
+            }
+            buckets.pop_front();
+        }
+        now
+    }
+
+    fn bucket(buckets: &mut VecDeque<(u64, u32, u32)>, now: u64) -> &mut (u64, u32, u32) {
+        if buckets.back().map(|b| b.0 != now).unwrap_or(true) {
+            buckets.push_back((now, 0, 0));
+        }
+        buckets.back_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_backoff_within_the_max_delay() {
+        let policy = RetryPolicy::new(&RetryConfig {
+            attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+        });
+
+        for attempt in 0..5 {
+            assert!(policy.backoff(attempt) <= Duration::from_millis(300));
+        }
+        assert!(policy.backoff(0) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn it_can_only_retry_idempotent_methods() {
+        let policy = RetryPolicy::new(&RetryConfig {
+            attempts: 1,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+        });
+
+        assert!(policy.is_retryable(&Method::GET));
+        assert!(policy.is_retryable(&Method::PUT));
+        assert!(!policy.is_retryable(&Method::POST));
+        assert!(!policy.is_retryable(&Method::PATCH));
+    }
+
+    #[test]
+    fn it_cannot_retry_when_the_budget_is_exhausted() {
+        let budget = RetryBudget::new(RetryBudgetConfig {
+            ratio: 0.2,
+            min_retries_per_second: 0,
+            window_seconds: 10,
+        });
+
+        for _ in 0..10 {
+            budget.deposit();
+        }
+
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+    }
+}
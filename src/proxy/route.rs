@@ -1,26 +1,264 @@
-use warp::{Filter, Reply};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use warp::{Filter, Rejection, Reply};
 use warp::filters::BoxedFilter;
-use warp_reverse_proxy::reverse_proxy_filter;
+use warp::filters::path::FullPath;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::hyper::body::Bytes;
+use warp_reverse_proxy::{extract_request_data_filter, QueryParameters};
 
 use crate::{Environment, WebResult};
 use crate::auth::json::claims::Claims;
-use crate::core::middlewares::authorization::authenticated_from_cookie;
+use crate::auth::json::principal::Principal;
+use crate::core::error::AppError;
+use crate::auth::role::Permission;
+use crate::core::middlewares::authorization::{
+    authenticate, authenticate_optionally, authorized, credentials, Access, AuthMethods,
+    Credentials,
+};
+use crate::core::middlewares::rate_limit::{client_ip, rate_limit_subject, RateLimitStatus};
+use crate::core::middlewares::with_env::with_env;
+use crate::proxy::balancer::{Instance, UpstreamPool};
+use crate::proxy::breaker::Permit;
+use crate::proxy::identity::{forward_identity, strip_credentials, REQUEST_ID_HEADER};
+use crate::proxy::json::status::PoolStatus;
+use crate::proxy::table::{Route, RouteTable};
 
 pub fn routes(env: Environment) -> BoxedFilter<(impl Reply,)> {
-    let customer_routes = warp::path!("api" / "v1" / "customers" / ..)
-        .and(authenticated_from_cookie(env))
-        .and(reverse_proxy_filter(
-            "".to_string(),
-            "http://127.0.0.1:3031".to_string(),
+    let table = env.route_table.clone();
+
+    let upstreams_route = warp::path!("api" / "v1" / "admin" / "upstreams")
+        .and(warp::get())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUpstreams),
         ))
-        .and_then(log_response);
+        .and(with_env(env.clone()))
+        .and_then(upstreams_handler);
+
+    let proxy_route = warp::path::full()
+        .and(warp::method())
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::any().map(move || table.clone()))
+        .and_then(find_route)
+        .and(credentials(&env.config.cookie))
+        .and(with_env(env.clone()))
+        .and_then(authorize_route)
+        .untuple_one()
+        .and(client_ip(env.config.trusted_proxies.clone()))
+        .and(with_env(env.clone()))
+        .and_then(limit_route)
+        .untuple_one()
+        .and(extract_request_data_filter())
+        .and(with_env(env))
+        .and_then(forward);
 
-    customer_routes.boxed()
+    upstreams_route.or(proxy_route).boxed()
 }
 
-async fn log_response(
-    claims: Claims,
-    response: warp::http::Response<warp::hyper::body::Bytes>,
-) -> WebResult<impl Reply> {
+async fn upstreams_handler(principal: Principal, env: Environment) -> WebResult<impl Reply> {
+    let status = env
+        .route_table
+        .pools()
+        .map(|pool| pool.status())
+        .collect::<Vec<PoolStatus>>();
+
+    Ok(warp::reply::json(&status))
+}
+
+async fn find_route(
+    path: FullPath,
+    method: Method,
+    host: Option<String>,
+    table: Arc<RouteTable>,
+) -> WebResult<Arc<Route>> {
+    table
+        .find(path.as_str(), &method, host.as_deref())
+        .ok_or_else(warp::reject::not_found)
+}
+
+/// Batch jobs authenticate with an api key, browsers with a session token.
+/// A public route still gets the identity of a signed in caller.
+async fn authorize_route(
+    route: Arc<Route>,
+    credentials: Credentials,
+    env: Environment,
+) -> Result<(Arc<Route>, Option<Claims>), Rejection> {
+    if !route.authenticated {
+        let principal = authenticate_optionally(credentials, AuthMethods::all(), env).await?;
+        return Ok((route, principal.map(Principal::into_claims)));
+    }
+
+    let principal = authenticate(credentials, AuthMethods::all(), env).await?;
+    route
+        .access
+        .check(principal.claims())
+        .map_err(warp::reject::custom)?;
+
+    Ok((route, Some(principal.into_claims())))
+}
+
+async fn limit_route(
+    route: Arc<Route>,
+    claims: Option<Claims>,
+    ip: Option<IpAddr>,
+    env: Environment,
+) -> Result<(Arc<Route>, Option<Claims>, Option<RateLimitStatus>), Rejection> {
+    let status = match &route.rate_limit {
+        None => None,
+        Some(config) => {
+            let subject = rate_limit_subject(config.key, claims.as_ref(), ip);
+            let status = env
+                .rate_limiter
+                .check(route.prefix.as_str(), subject.as_str(), config)
+                .map_err(warp::reject::custom)?;
+            Some(status)
+        }
+    };
+
+    Ok((route, claims, status))
+}
+
+async fn forward(
+    route: Arc<Route>,
+    claims: Option<Claims>,
+    rate_limit: Option<RateLimitStatus>,
+    uri: FullPath,
+    params: QueryParameters,
+    method: Method,
+    mut headers: HeaderMap,
+    body: Bytes,
+    env: Environment,
+) -> WebResult<warp::http::Response<Bytes>> {
+    // Upstreams get the identity, not the credentials of the gateway.
+    strip_credentials(&mut headers, &env.config.cookie);
+
+    let request_id = forward_identity(
+        &mut headers,
+        claims.as_ref(),
+        env.config.identity_signing_key.as_deref(),
+    );
+
+    let retry = route
+        .retry
+        .as_ref()
+        .filter(|policy| policy.is_retryable(&method));
+
+    route.retry_budget.deposit();
+
+    let attempts = async {
+        let mut attempt = 0;
+        loop {
+            let response = forward_once(
+                &route,
+                &claims,
+                uri.as_str(),
+                params.as_deref(),
+                method.clone(),
+                &headers,
+                body.clone(),
+            )
+            .await;
+
+            let policy = match retry {
+                Some(policy) if attempt < policy.attempts && should_retry(&response) => policy,
+                _ => return response,
+            };
+
+            if !route.retry_budget.try_withdraw() {
+                tracing::warn!("retry budget exhausted, give up {}.", uri.as_str());
+                return response;
+            }
+
+            tokio::time::sleep(policy.backoff(attempt)).await;
+            attempt += 1;
+        }
+    };
+
+    let mut response = tokio::time::timeout(route.total_timeout, attempts)
+        .await
+        .unwrap_or(Err(AppError::UpstreamTimeout))
+        .map_err(warp::reject::custom)?;
+
+    if let Some(status) = rate_limit {
+        status.apply(&mut response);
+    }
+    if let Ok(value) = request_id.parse() {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
     Ok(response)
 }
+
+fn should_retry(response: &Result<warp::http::Response<Bytes>, AppError>) -> bool {
+    match response {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(AppError::CircuitOpen) => false,
+        Err(_) => true,
+    }
+}
+
+async fn forward_once(
+    route: &Route,
+    claims: &Option<Claims>,
+    path: &str,
+    query: Option<&str>,
+    method: Method,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<warp::http::Response<Bytes>, AppError> {
+    // Dropped unrecorded, for instance by the total timeout, the permit
+    // counts as a failure.
+    let permit = match &route.pool.circuit_breaker {
+        Some(breaker) => Some(breaker.try_acquire()?),
+        None => None,
+    };
+
+    let key = claims.as_ref().map(|c| c.sub.as_str());
+    let instance = route
+        .pool
+        .select(key)
+        .ok_or(AppError::UpstreamUnavailable)?;
+    let _guard = instance.acquire();
+
+    let url = route.upstream_url(instance.url.as_str(), path, query);
+    let response = route
+        .client
+        .send(url.as_str(), method, headers, body)
+        .await;
+
+    log_response(claims, &route.pool, &instance, permit, response).await
+}
+
+async fn log_response(
+    claims: &Option<Claims>,
+    pool: &UpstreamPool,
+    instance: &Instance,
+    permit: Option<Permit<'_>>,
+    response: Result<warp::http::Response<Bytes>, AppError>,
+) -> Result<warp::http::Response<Bytes>, AppError> {
+    if let Some(permit) = permit {
+        permit.record(matches!(&response, Ok(r) if !r.status().is_server_error()));
+    }
+
+    match response {
+        Ok(response) => {
+            if response.status().is_server_error() {
+                pool.report_failure(instance);
+            } else {
+                pool.report_success(instance);
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            tracing::warn!("proxy to {} failed: {:?}", instance.url, e);
+            pool.report_failure(instance);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use warp::http::Method;
+
+use crate::core::middlewares::authorization::Access;
+use crate::proxy::balancer::UpstreamPool;
+use crate::proxy::client::UpstreamClient;
+use crate::proxy::json::config::{ProxyConfig, RateLimitConfig, RouteConfig};
+use crate::proxy::retry::{RetryBudget, RetryPolicy};
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub prefix: String,
+    pub service: String,
+    pub pool: Arc<UpstreamPool>,
+    pub methods: Vec<Method>,
+    pub hosts: Vec<String>,
+    pub authenticated: bool,
+    pub access: Access,
+    pub rewrite: Option<String>,
+    pub client: UpstreamClient,
+    pub total_timeout: Duration,
+    pub retry: Option<RetryPolicy>,
+    pub retry_budget: Arc<RetryBudget>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl Route {
+    fn new(
+        config: &RouteConfig,
+        pool: Arc<UpstreamPool>,
+        retry_budget: Arc<RetryBudget>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            prefix: normalize_prefix(config.prefix.as_str()),
+            service: config.service.clone(),
+            pool,
+            methods: config.methods()?,
+            hosts: config.hosts.iter().map(|h| h.to_lowercase()).collect(),
+            authenticated: config.authenticated,
+            access: Access {
+                roles: config.roles.clone(),
+                permissions: config.permissions.clone(),
+            },
+            rewrite: config.rewrite.clone(),
+            client: UpstreamClient::new(&config.timeout),
+            total_timeout: Duration::from_millis(config.timeout.total_ms),
+            retry: config.retry.as_ref().map(RetryPolicy::new),
+            retry_budget,
+            rate_limit: config.rate_limit.clone(),
+        })
+    }
+
+    fn is_match(&self, path: &str, method: &Method, host: Option<&str>) -> bool {
+        let path_matched = self.prefix == "/"
+            || path == self.prefix
+            || path
+                .strip_prefix(self.prefix.as_str())
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or(false);
+
+        let method_matched = self.methods.is_empty() || self.methods.contains(method);
+
+        let host_matched = self.hosts.is_empty()
+            || host
+                .map(|h| h.split(':').next().unwrap_or(h).to_lowercase())
+                .map(|h| self.hosts.contains(&h))
+                .unwrap_or(false);
+
+        path_matched && method_matched && host_matched
+    }
+
+    /// Builds the url of `path` on the instance `url`, replacing the prefix
+    /// when the route has a rewrite.
+    pub fn upstream_url(&self, url: &str, path: &str, query: Option<&str>) -> String {
+        let path = match &self.rewrite {
+            None => path.to_string(),
+            Some(rewrite) => {
+                let rest = path.strip_prefix(self.prefix.as_str()).unwrap_or(path);
+                // Both sides may be empty or carry their own slashes.
+                let segments = [rewrite.trim_matches('/'), rest.trim_start_matches('/')]
+                    .iter()
+                    .filter(|segment| !segment.is_empty())
+                    .copied()
+                    .collect::<Vec<_>>();
+                format!("/{}", segments.join("/"))
+            }
+        };
+
+        let url = format!(
+            "{}/{}",
+            url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+
+        match query {
+            Some(query) => format!("{}?{}", url, query),
+            None => url,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<Arc<Route>>,
+    pools: HashMap<String, Arc<UpstreamPool>>,
+}
+
+impl RouteTable {
+    /// Fails on the first route which can't be built, `ProxyConfig::validate`
+    /// reports all of them.
+    pub fn new(config: &ProxyConfig) -> Result<Self, String> {
+        let pools = config
+            .services
+            .iter()
+            .map(|service| Ok((service.name.clone(), Arc::new(UpstreamPool::new(service)?))))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+
+        let retry_budget = Arc::new(RetryBudget::new(config.retry_budget.clone()));
+
+        let mut routes = config
+            .routes
+            .iter()
+            .map(|route| {
+                let pool = pools.get(&route.service).ok_or_else(|| {
+                    format!("route {} uses the unknown service {}.", route.prefix, route.service)
+                })?;
+                Route::new(route, pool.clone(), retry_budget.clone()).map(Arc::new)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The longest prefix wins, so more specific routes are checked first.
+        routes.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+
+        Ok(Self { routes, pools })
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &Arc<UpstreamPool>> {
+        self.pools.values()
+    }
+
+    pub fn find(&self, path: &str, method: &Method, host: Option<&str>) -> Option<Arc<Route>> {
+        self.routes
+            .iter()
+            .find(|route| route.is_match(path, method, host))
+            .cloned()
+    }
+}
+
+fn normalize_prefix(prefix: &str) -> String {
+    format!("/{}", prefix.trim_matches('/'))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::proxy::json::config::{
+        InstanceConfig, RateLimitKey, RetryBudgetConfig, ServiceConfig, Strategy, TimeoutConfig,
+    };
+
+    use super::*;
+
+    fn config() -> ProxyConfig {
+        ProxyConfig {
+            services: vec![
+                ServiceConfig {
+                    name: "customers".to_string(),
+                    strategy: Strategy::RoundRobin,
+                    instances: vec![InstanceConfig {
+                        url: "http://127.0.0.1:3031".to_string(),
+                        weight: 1,
+                    }],
+                    health_check: Default::default(),
+                    circuit_breaker: None,
+                },
+                ServiceConfig {
+                    name: "orders".to_string(),
+                    strategy: Strategy::RoundRobin,
+                    instances: vec![InstanceConfig {
+                        url: "http://127.0.0.1:3032/".to_string(),
+                        weight: 1,
+                    }],
+                    health_check: Default::default(),
+                    circuit_breaker: None,
+                },
+            ],
+            routes: vec![
+                RouteConfig {
+                    prefix: "/api/v1/customers".to_string(),
+                    service: "customers".to_string(),
+                    methods: vec![],
+                    hosts: vec![],
+                    authenticated: true,
+                    roles: vec![],
+                    permissions: vec![],
+                    rewrite: None,
+                    timeout: TimeoutConfig::default(),
+                    retry: None,
+                    rate_limit: None,
+                },
+                RouteConfig {
+                    prefix: "api/v1/customers/public/".to_string(),
+                    service: "orders".to_string(),
+                    methods: vec!["get".to_string()],
+                    hosts: vec!["Example.com".to_string()],
+                    authenticated: false,
+                    roles: vec![],
+                    permissions: vec![],
+                    rewrite: Some("/v2".to_string()),
+                    timeout: TimeoutConfig::default(),
+                    retry: None,
+                    rate_limit: None,
+                },
+            ],
+            retry_budget: RetryBudgetConfig::default(),
+        }
+    }
+
+    fn table() -> RouteTable {
+        RouteTable::new(&config()).unwrap()
+    }
+
+    #[test]
+    fn it_can_find_route_by_prefix() {
+        let route = table().find("/api/v1/customers/1", &Method::POST, None);
+
+        assert!(route.is_some());
+        let route = route.unwrap();
+        assert_eq!(route.service, "customers");
+        assert_eq!(
+            route.upstream_url("http://127.0.0.1:3031", "/api/v1/customers/1", Some("a=1")),
+            "http://127.0.0.1:3031/api/v1/customers/1?a=1"
+        );
+    }
+
+    #[test]
+    fn it_prefers_the_longest_prefix() {
+        let route = table()
+            .find("/api/v1/customers/public/1", &Method::GET, Some("example.com:3030"))
+            .unwrap();
+
+        assert_eq!(route.service, "orders");
+        assert_eq!(
+            route.upstream_url("http://127.0.0.1:3032/", "/api/v1/customers/public/1", None),
+            "http://127.0.0.1:3032/v2/1"
+        );
+    }
+
+    #[test]
+    fn it_cannot_find_route_when_method_or_host_mismatch() {
+        let table = table();
+
+        let route = table
+            .find("/api/v1/customers/public/1", &Method::POST, Some("example.com"))
+            .unwrap();
+        assert_eq!(route.service, "customers");
+
+        let route = table
+            .find("/api/v1/customers/public/1", &Method::GET, Some("other.com"))
+            .unwrap();
+        assert_eq!(route.service, "customers");
+
+        assert!(table.find("/api/v1/customersx", &Method::GET, None).is_none());
+    }
+
+    #[test]
+    fn it_cannot_build_route_with_invalid_method() {
+        let mut config = config();
+        config.routes[1].methods = vec!["GE T".to_string()];
+
+        assert!(RouteTable::new(&config).is_err());
+        assert!(config.validate().unwrap_err().iter().any(|e| e.contains("GE T")));
+    }
+
+    #[test]
+    fn it_can_rewrite_prefix_with_single_slash() {
+        let mut config = config();
+        config.routes[0].prefix = "/".to_string();
+        config.routes[0].rewrite = Some("/v2".to_string());
+        config.routes[1].rewrite = Some("/".to_string());
+        let table = RouteTable::new(&config).unwrap();
+
+        let route = table.find("/foo", &Method::GET, None).unwrap();
+        assert_eq!(
+            route.upstream_url("http://127.0.0.1:3031", "/foo", None),
+            "http://127.0.0.1:3031/v2/foo"
+        );
+        assert_eq!(
+            route.upstream_url("http://127.0.0.1:3031", "/", None),
+            "http://127.0.0.1:3031/v2"
+        );
+
+        let route = table
+            .find("/api/v1/customers/public/1/", &Method::GET, Some("example.com"))
+            .unwrap();
+        assert_eq!(
+            route.upstream_url("http://127.0.0.1:3032", "/api/v1/customers/public/1/", None),
+            "http://127.0.0.1:3032/1/"
+        );
+    }
+
+    #[test]
+    fn it_cannot_validate_rate_limit_of_zero() {
+        let mut config = config();
+        config.routes[0].rate_limit = Some(RateLimitConfig {
+            key: RateLimitKey::User,
+            limit: 0,
+            window_seconds: 60,
+        });
+
+        assert!(config.validate().unwrap_err().iter().any(|e| e.contains("rate limit")));
+    }
+
+    #[test]
+    fn it_cannot_validate_timeout_of_zero() {
+        let mut config = config();
+        config.routes[0].timeout.total_ms = 0;
+        config.retry_budget.ratio = 0.0;
+
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.contains("timeouts")));
+        assert!(errors.iter().any(|e| e.contains("retry budget")));
+    }
+
+    #[test]
+    fn it_cannot_build_service_with_instance_of_weight_zero() {
+        let mut config = config();
+        config.services[0].instances[0].weight = 0;
+
+        assert!(RouteTable::new(&config).is_err());
+        assert!(config.validate().unwrap_err().iter().any(|e| e.contains("weight")));
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::{Config, Environment, WebResult};
+use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::repo::AuthRepository;
+use crate::core::error::AppError;
+use crate::user::json::mfa::{EnrollMfaResponse, Mfa, MfaCodeRequest, RecoveryCodesResponse};
+use crate::user::repo::UserRepository;
+use crate::user::totp;
+
+// A used totp step is remembered a bit longer than the codes are accepted.
+const USED_STEP_SECONDS: usize = 120;
+
+pub async fn enroll_mfa_handler(principal: Principal, env: Environment) -> WebResult<impl Reply> {
+    enroll_mfa(env.user_repo, &env.config, principal.claims())
+        .await
+        .map(|response| warp::reply::json(&response))
+        .map_err(warp::reject::custom)
+}
+
+/// Generates a new secret, it's only used once a code of it is verified.
+async fn enroll_mfa(
+    user_repo: Arc<impl UserRepository>,
+    config: &Config,
+    claims: &Claims,
+) -> Result<EnrollMfaResponse, AppError> {
+    let user_id = user_id(claims)?;
+
+    if let Some(Mfa { enabled: true, .. }) = user_repo.get_mfa(&user_id).await? {
+        return Err(AppError::MfaAlreadyEnabled);
+    }
+
+    let user = user_repo
+        .get(&user_id)
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+
+    let secret = totp::generate_secret();
+    user_repo.save_mfa_secret(&user_id, &secret).await?;
+
+    Ok(EnrollMfaResponse {
+        secret: totp::base32(&secret),
+        otpauth_uri: totp::otpauth_uri(config.mfa_issuer.as_str(), user.name.as_str(), &secret),
+    })
+}
+
+pub async fn verify_mfa_handler(
+    principal: Principal,
+    req: MfaCodeRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    verify_mfa(env.user_repo, env.auth_repo, principal.claims(), req.code.as_str())
+        .await
+        .map(|response| warp::reply::json(&response))
+        .map_err(warp::reject::custom)
+}
+
+/// Enables the enrolled secret and returns new recovery codes.
+async fn verify_mfa(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    code: &str,
+) -> Result<RecoveryCodesResponse, AppError> {
+    let user_id = user_id(claims)?;
+
+    let mfa = match user_repo.get_mfa(&user_id).await? {
+        None => return Err(AppError::MfaNotEnrolled),
+        Some(Mfa { enabled: true, .. }) => return Err(AppError::MfaAlreadyEnabled),
+        Some(mfa) => mfa,
+    };
+
+    verify_totp(&*auth_repo, &mfa, code)?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    user_repo
+        .enable_mfa(
+            &user_id,
+            recovery_codes
+                .iter()
+                .map(|code| totp::hash_recovery_code(code))
+                .collect(),
+        )
+        .await?;
+
+    Ok(RecoveryCodesResponse { recovery_codes })
+}
+
+pub async fn disable_mfa_handler(
+    principal: Principal,
+    req: MfaCodeRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    disable_mfa(env.user_repo, env.auth_repo, principal.claims(), req.code.as_str())
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+async fn disable_mfa(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    code: &str,
+) -> Result<(), AppError> {
+    let user_id = user_id(claims)?;
+
+    let mfa = user_repo
+        .get_mfa(&user_id)
+        .await?
+        .filter(|mfa| mfa.enabled)
+        .ok_or(AppError::MfaNotEnrolled)?;
+
+    verify_second_factor(&*user_repo, &*auth_repo, &mfa, code).await?;
+
+    user_repo.disable_mfa(&user_id).await
+}
+
+/// Accepts a totp code of the secret, or one of the recovery codes which
+/// can't be used again.
+pub async fn verify_second_factor(
+    user_repo: &impl UserRepository,
+    auth_repo: &impl AuthRepository,
+    mfa: &Mfa,
+    code: &str,
+) -> Result<(), AppError> {
+    if verify_totp(auth_repo, mfa, code).is_ok() {
+        return Ok(());
+    }
+
+    let consumed = user_repo
+        .consume_recovery_code(&mfa.user_id, totp::hash_recovery_code(code).as_str())
+        .await?;
+
+    if consumed {
+        Ok(())
+    } else {
+        Err(AppError::InvalidMfaCode)
+    }
+}
+
+fn verify_totp(auth_repo: &impl AuthRepository, mfa: &Mfa, code: &str) -> Result<(), AppError> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let step = totp::verify(&mfa.secret, code.trim(), now).ok_or(AppError::InvalidMfaCode)?;
+
+    // A code seen on the wire can't be replayed while it's still valid.
+    let first_use = auth_repo
+        .consume_totp_step(mfa.user_id, step, USED_STEP_SECONDS)
+        .map_err(|_| AppError::InvalidMfaCode)?;
+
+    if first_use {
+        Ok(())
+    } else {
+        Err(AppError::InvalidMfaCode)
+    }
+}
+
+fn user_id(claims: &Claims) -> Result<Uuid, AppError> {
+    Uuid::parse_str(claims.sub.as_str()).map_err(|_| AppError::AuthorizeFailed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auth::repo::MockAuthRepository;
+    use crate::user::repo::MockUserRepository;
+
+    use super::*;
+
+    fn mfa(user_id: Uuid) -> Mfa {
+        Mfa {
+            user_id,
+            secret: b"12345678901234567890".to_vec(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn it_can_verify_recovery_code() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo
+            .expect_consume_recovery_code()
+            .withf(|_, code_hash| code_hash == totp::hash_recovery_code("abcd2345"))
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let result = runtime.block_on(verify_second_factor(
+            &user_mock_repo,
+            &auth_mock_repo,
+            &mfa(Uuid::new_v4()),
+            "abcd2345",
+        ));
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn it_cannot_replay_totp_code() {
+        let mut auth_mock_repo = MockAuthRepository::new();
+        let mfa = mfa(Uuid::new_v4());
+        let step = chrono::Utc::now().timestamp() as u64 / 30;
+
+        auth_mock_repo
+            .expect_consume_totp_step()
+            .returning(|_, _, _| Ok(false));
+
+        let result = verify_totp(
+            &auth_mock_repo,
+            &mfa,
+            totp::code_at(&mfa.secret, step).as_str(),
+        );
+
+        assert_eq!(result.unwrap_err(), AppError::InvalidMfaCode);
+    }
+}
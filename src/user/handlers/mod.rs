@@ -1 +1,3 @@
+pub mod mfa;
+pub mod password;
 pub mod v1;
\ No newline at end of file
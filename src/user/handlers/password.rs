@@ -0,0 +1,416 @@
+use std::sync::Arc;
+
+use tracing::warn;
+use uuid::Uuid;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::{Config, Environment, WebResult};
+use crate::auth::handlers::create_random_token;
+use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::repo::AuthRepository;
+use crate::core::audit::audit;
+use crate::core::error::AppError;
+use crate::core::notifier::Notifier;
+use crate::core::util::{hash_password, is_password_hash, verify_password};
+use crate::user::json::password::{
+    ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest,
+};
+use crate::user::json::user::UserStatus;
+use crate::user::repo::UserRepository;
+use crate::user::validate::check_password;
+
+// A reset token is good for half an hour.
+const RESET_TOKEN_SECONDS: usize = 30 * 60;
+
+pub async fn change_password_handler(
+    principal: Principal,
+    req: ChangePasswordRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    change_password(env.user_repo, env.auth_repo, &env.config, principal.claims(), req)
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+/// Replaces the password after checking the old one, the other sessions of
+/// the user are revoked.
+async fn change_password(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    claims: &Claims,
+    req: ChangePasswordRequest,
+) -> Result<(), AppError> {
+    let user_id = Uuid::parse_str(claims.sub.as_str()).map_err(|_| AppError::AuthorizeFailed)?;
+    check_password(req.new_password.as_str())?;
+
+    let simple_user = user_repo
+        .get(&user_id)
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+    let user = user_repo
+        .get_by_name(simple_user.name.as_str())
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+
+    if !verify_password(user.password.as_str(), req.old_password.as_str(), config) {
+        return Err(AppError::AuthorizeFailed);
+    }
+
+    set_password(&*user_repo, config, &user_id, req.new_password.as_str()).await?;
+
+    let sessions = auth_repo
+        .list(user_id)
+        .map_err(|_| AppError::DatabaseError)?;
+    for session in sessions.iter().filter(|s| s.id != claims.jti) {
+        auth_repo
+            .expire(session.id.as_str())
+            .map_err(|_| AppError::DatabaseError)?;
+    }
+
+    audit("password_changed", claims.sub.as_str(), Some(claims.sub.as_str()), None);
+    Ok(())
+}
+
+pub async fn forgot_password_handler(
+    req: ForgotPasswordRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    forgot_password(env.user_repo, env.auth_repo, &*env.notifier, req)
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::ACCEPTED))
+        .map_err(warp::reject::custom)
+}
+
+/// Sends a reset token to the user. The response doesn't tell whether the
+/// user exists, nor whether it can have a password.
+async fn forgot_password(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    notifier: &(dyn Notifier + Send + Sync),
+    req: ForgotPasswordRequest,
+) -> Result<(), AppError> {
+    let user = match user_repo.get_by_name(req.name.as_str()).await? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    // A reset must not give a password to service accounts or users of
+    // external identities, nor bring back a disabled user.
+    if UserStatus::from(user.status) != UserStatus::Active
+        || user.service_account
+        || !is_password_hash(user.password.as_str())
+    {
+        return Ok(());
+    }
+    let user_id = user.id.ok_or(AppError::UserNotExist)?;
+
+    let token = create_random_token();
+    auth_repo
+        .create_password_reset(token.as_str(), user_id, RESET_TOKEN_SECONDS)
+        .map_err(|_| AppError::DatabaseError)?;
+
+    if let Err(e) = notifier
+        .send_password_reset(&user.into(), token.as_str(), RESET_TOKEN_SECONDS)
+        .await
+    {
+        warn!("password reset of {} isn't delivered: {}", user_id, e);
+    }
+
+    audit("password_reset_requested", user_id.to_string().as_str(), None, None);
+    Ok(())
+}
+
+pub async fn reset_password_handler(
+    req: ResetPasswordRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    reset_password(env.user_repo, env.auth_repo, &env.config, req)
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+/// Sets a new password with a reset token, every session of the user is
+/// revoked.
+async fn reset_password(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    config: &Config,
+    req: ResetPasswordRequest,
+) -> Result<(), AppError> {
+    // Checked first, a rejected password doesn't use up the token.
+    check_password(req.new_password.as_str())?;
+
+    let user_id = auth_repo
+        .take_password_reset(req.token.as_str())
+        .ok_or(AppError::TokenNotExist)?;
+
+    set_password(&*user_repo, config, &user_id, req.new_password.as_str()).await?;
+
+    auth_repo
+        .expire_all(user_id)
+        .map_err(|_| AppError::DatabaseError)?;
+
+    audit("password_reset", user_id.to_string().as_str(), None, None);
+    Ok(())
+}
+
+async fn set_password(
+    user_repo: &impl UserRepository,
+    config: &Config,
+    user_id: &Uuid,
+    password: &str,
+) -> Result<(), AppError> {
+    let encrypt_password = hash_password(password, config).ok_or(AppError::HashPasswordFailed)?;
+
+    if user_repo
+        .update_password(user_id, encrypt_password.as_str())
+        .await?
+    {
+        Ok(())
+    } else {
+        Err(AppError::UserNotExist)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use crate::auth::json::session::{Session, SessionMetadata};
+    use crate::auth::repo::MockAuthRepository;
+    use crate::user::json::user::{SimpleUser, User};
+    use crate::user::repo::MockUserRepository;
+    use crate::AppResult;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingNotifier {
+        tokens: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn send_password_reset(
+            &self,
+            _: &SimpleUser,
+            token: &str,
+            _: usize,
+        ) -> AppResult<()> {
+            self.tokens.lock().unwrap().push(token.to_string());
+            Ok(())
+        }
+    }
+
+    fn user(id: Uuid) -> User {
+        User {
+            id: Some(id),
+            name: "boris".to_string(),
+            password: hash_password("123", &Config::new().unwrap()).unwrap(),
+            role: 0,
+            created_at: None,
+            updated_at: None,
+            status: 0,
+            deleted_at: None,
+            service_account: false,
+        }
+    }
+
+    #[test]
+    fn it_can_change_password_and_revoke_other_sessions() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let user_id = Uuid::new_v4();
+        let current = Session::new(user_id, SessionMetadata::default());
+        let other = Session::new(user_id, SessionMetadata::default());
+        let claims = Claims::new(user_id.to_string(), 0, 0, current.id.clone());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo
+            .expect_get()
+            .returning(move |_| Ok(Some(user(user_id).into())));
+        user_mock_repo
+            .expect_get_by_name()
+            .returning(move |_| Ok(Some(user(user_id))));
+        user_mock_repo
+            .expect_update_password()
+            .times(1)
+            .returning(|_, _| Ok(true));
+
+        let sessions = vec![current, other.clone()];
+        auth_mock_repo
+            .expect_list()
+            .returning(move |_| Ok(sessions.clone()));
+        auth_mock_repo
+            .expect_expire()
+            .withf(move |session_id| session_id == other.id)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = ChangePasswordRequest {
+            old_password: "123".to_string(),
+            new_password: "Correct2".to_string(),
+        };
+        let response = runtime.block_on(change_password(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &claims,
+            req,
+        ));
+
+        assert!(response.is_ok());
+    }
+
+    #[test]
+    fn it_cannot_change_password_with_wrong_old_password() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+        let user_id = Uuid::new_v4();
+        let claims = Claims::new(user_id.to_string(), 0, 0, "session".to_string());
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo
+            .expect_get()
+            .returning(move |_| Ok(Some(user(user_id).into())));
+        user_mock_repo
+            .expect_get_by_name()
+            .returning(move |_| Ok(Some(user(user_id))));
+        user_mock_repo.expect_update_password().never();
+
+        let req = ChangePasswordRequest {
+            old_password: "wrong".to_string(),
+            new_password: "Correct2".to_string(),
+        };
+        let response = runtime.block_on(change_password(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            &claims,
+            req,
+        ));
+
+        assert_eq!(response.unwrap_err(), AppError::AuthorizeFailed);
+    }
+
+    #[test]
+    fn it_can_send_reset_token_only_to_existing_user() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let notifier = RecordingNotifier::default();
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo
+            .expect_get_by_name()
+            .returning(|name| Ok((name == "boris").then(|| user(Uuid::new_v4()))));
+        auth_mock_repo
+            .expect_create_password_reset()
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+
+        let (user_repo, auth_repo) = (Arc::new(user_mock_repo), Arc::new(auth_mock_repo));
+        for name in ["boris", "nobody"] {
+            let req = ForgotPasswordRequest {
+                name: name.to_string(),
+            };
+            let response = runtime.block_on(forgot_password(
+                user_repo.clone(),
+                auth_repo.clone(),
+                &notifier,
+                req,
+            ));
+            assert!(response.is_ok());
+        }
+
+        assert_eq!(notifier.tokens.lock().unwrap().len(), 1);
+    }
+
+    fn assert_no_reset_token(user: User) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let notifier = RecordingNotifier::default();
+
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        user_mock_repo
+            .expect_get_by_name()
+            .returning(move |_| Ok(Some(user.clone())));
+        auth_mock_repo.expect_create_password_reset().never();
+
+        let req = ForgotPasswordRequest {
+            name: "boris".to_string(),
+        };
+        let response = runtime.block_on(forgot_password(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &notifier,
+            req,
+        ));
+
+        assert!(response.is_ok());
+        assert!(notifier.tokens.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_cannot_send_reset_token_to_service_account() {
+        let mut user = user(Uuid::new_v4());
+        user.service_account = true;
+
+        assert_no_reset_token(user);
+    }
+
+    #[test]
+    fn it_cannot_send_reset_token_to_external_user() {
+        let mut user = user(Uuid::new_v4());
+        user.password = "!".to_string();
+
+        assert_no_reset_token(user);
+    }
+
+    #[test]
+    fn it_cannot_send_reset_token_to_inactive_user() {
+        for status in [UserStatus::Disabled, UserStatus::Deleted] {
+            let mut user = user(Uuid::new_v4());
+            user.status = status.into();
+
+            assert_no_reset_token(user);
+        }
+    }
+
+    #[test]
+    fn it_cannot_reset_password_with_used_token() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = Config::new().unwrap();
+
+        let user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+
+        auth_mock_repo
+            .expect_take_password_reset()
+            .returning(|_| None);
+
+        let req = ResetPasswordRequest {
+            token: "used".to_string(),
+            new_password: "Correct2".to_string(),
+        };
+        let response = runtime.block_on(reset_password(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &config,
+            req,
+        ));
+
+        assert_eq!(response.unwrap_err(), AppError::TokenNotExist);
+    }
+}
@@ -1,13 +1,25 @@
 use std::sync::Arc;
 
+use uuid::Uuid;
+use warp::http::StatusCode;
 use warp::Reply;
 
 use crate::{Config, Environment, WebResult};
+use crate::auth::json::claims::Claims;
+use crate::auth::json::principal::Principal;
+use crate::auth::repo::AuthRepository;
+use crate::auth::role::Role;
+use crate::core::audit::audit;
 use crate::core::error::AppError;
 use crate::core::util::hash_password;
-use crate::user::json::request::CreateUserRequest;
-use crate::user::json::user::SimpleUser;
-use crate::user::repo::UserRepository;
+use crate::user::json::request::{
+    CreateServiceAccountRequest, CreateUserRequest, ListUsersQuery, UpdateUserRequest,
+};
+use crate::user::json::user::{SimpleUser, UserPage, UserStatus};
+use crate::user::repo::{page_updated_at, UserRepository};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
 
 pub async fn create_user_handler(
     req: CreateUserRequest,
@@ -24,6 +36,8 @@ async fn create_user(
     user_repo: Arc<impl UserRepository>,
     config: &Config,
 ) -> Result<SimpleUser, AppError> {
+    req.validate()?;
+
     let encrypt_password = hash_password(req.password.as_str(), config);
 
     if encrypt_password.is_none() {
@@ -34,14 +48,202 @@ async fn create_user(
         .create(
             req.name.as_str(),
             encrypt_password.unwrap().as_str(),
-            req.role,
+            Role::User as i16,
         )
         .await
 }
 
+pub async fn create_service_account_handler(
+    principal: Principal,
+    req: CreateServiceAccountRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    create_service_account(env.user_repo, principal.claims(), req)
+        .await
+        .map(|simple_user| warp::reply::json(&simple_user))
+        .map_err(warp::reject::custom)
+}
+
+async fn create_service_account(
+    user_repo: Arc<impl UserRepository>,
+    claims: &Claims,
+    req: CreateServiceAccountRequest,
+) -> Result<SimpleUser, AppError> {
+    req.validate()?;
+
+    let simple_user = user_repo
+        .create_service_account(req.name.as_str(), req.role)
+        .await?;
+
+    audit(
+        "service_account_created",
+        simple_user.id.to_string().as_str(),
+        Some(claims.sub.as_str()),
+        None,
+    );
+    Ok(simple_user)
+}
+
+pub async fn get_user_handler(id: Uuid, _: Principal, env: Environment) -> WebResult<impl Reply> {
+    get_user(env.user_repo, &id)
+        .await
+        .map(|simple_user| warp::reply::json(&simple_user))
+        .map_err(warp::reject::custom)
+}
+
+/// Deleted users are hidden like in `list_users`.
+async fn get_user(user_repo: Arc<impl UserRepository>, id: &Uuid) -> Result<SimpleUser, AppError> {
+    user_repo
+        .get(id)
+        .await?
+        .filter(|user| UserStatus::from(user.status) != UserStatus::Deleted)
+        .ok_or(AppError::UserNotExist)
+}
+
+pub async fn list_users_handler(
+    query: ListUsersQuery,
+    _: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    list_users(env.user_repo, query)
+        .await
+        .map(|page| warp::reply::json(&page))
+        .map_err(warp::reject::custom)
+}
+
+async fn list_users(
+    user_repo: Arc<impl UserRepository>,
+    query: ListUsersQuery,
+) -> Result<UserPage, AppError> {
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // A cursor without an id lists the users of `updated_at` again rather
+    // than skipping them.
+    let cursor = query
+        .updated_at
+        .map(|updated_at| (updated_at, query.id.unwrap_or_else(Uuid::nil)));
+    let users = user_repo
+        .list(query.keyword.filter(|keyword| !keyword.is_empty()), cursor, page_size)
+        .await?;
+
+    let next = users.last().filter(|_| users.len() == page_size);
+
+    Ok(UserPage {
+        next_updated_at: next.map(page_updated_at),
+        next_id: next.map(|user| user.id),
+        users,
+    })
+}
+
+pub async fn update_user_handler(
+    id: Uuid,
+    _: Principal,
+    req: UpdateUserRequest,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    update_user(env.user_repo, env.auth_repo, &id, req)
+        .await
+        .map(|simple_user| warp::reply::json(&simple_user))
+        .map_err(warp::reject::custom)
+}
+
+async fn update_user(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    id: &Uuid,
+    req: UpdateUserRequest,
+) -> Result<SimpleUser, AppError> {
+    req.validate()?;
+
+    let simple_user = user_repo
+        .update(id, req.name.as_deref(), req.role)
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+
+    // The role is carried by the tokens, they are revoked so the user has to
+    // log in again with the new one.
+    if req.role.is_some() {
+        auth_repo
+            .expire_all(*id)
+            .map_err(|_| AppError::DatabaseError)?;
+    }
+    Ok(simple_user)
+}
+
+pub async fn delete_user_handler(
+    id: Uuid,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    change_status(env.user_repo, env.auth_repo, principal.claims(), &id, UserStatus::Deleted)
+        .await
+        .map(|_| warp::reply::with_status("", StatusCode::NO_CONTENT))
+        .map_err(warp::reject::custom)
+}
+
+pub async fn disable_user_handler(
+    id: Uuid,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    change_status(env.user_repo, env.auth_repo, principal.claims(), &id, UserStatus::Disabled)
+        .await
+        .map(|simple_user| warp::reply::json(&simple_user))
+        .map_err(warp::reject::custom)
+}
+
+pub async fn activate_user_handler(
+    id: Uuid,
+    principal: Principal,
+    env: Environment,
+) -> WebResult<impl Reply> {
+    change_status(env.user_repo, env.auth_repo, principal.claims(), &id, UserStatus::Active)
+        .await
+        .map(|simple_user| warp::reply::json(&simple_user))
+        .map_err(warp::reject::custom)
+}
+
+/// A disabled or deleted user loses its sessions at once, a user can't
+/// change its own status.
+async fn change_status(
+    user_repo: Arc<impl UserRepository>,
+    auth_repo: Arc<impl AuthRepository>,
+    claims: &Claims,
+    id: &Uuid,
+    status: UserStatus,
+) -> Result<SimpleUser, AppError> {
+    if claims.sub == id.to_string() {
+        return Err(AppError::Forbidden);
+    }
+
+    let simple_user = user_repo
+        .set_status(id, status)
+        .await?
+        .ok_or(AppError::UserNotExist)?;
+
+    let blocked = match status {
+        UserStatus::Active => auth_repo.unblock_user(*id),
+        UserStatus::Disabled | UserStatus::Deleted => auth_repo.block_user(*id),
+    };
+    blocked.map_err(|_| AppError::DatabaseError)?;
+
+    let event = match status {
+        UserStatus::Active => "user_activated",
+        UserStatus::Disabled => "user_disabled",
+        UserStatus::Deleted => "user_deleted",
+    };
+    audit(event, id.to_string().as_str(), Some(claims.sub.as_str()), None);
+
+    Ok(simple_user)
+}
+
 #[cfg(test)]
 mod test {
     use crate::Config;
+    use crate::auth::repo::MockAuthRepository;
     use crate::user::repo::MockUserRepository;
 
     use super::*;
@@ -51,11 +253,10 @@ mod test {
         let runtime = tokio::runtime::Runtime::new().unwrap();
         let req = CreateUserRequest {
             name: "boris".to_string(),
-            password: "123".to_string(),
-            role: 0,
+            password: "Correct1".to_string(),
         };
         let mut user_mock_repo = MockUserRepository::new();
-        let config = Config::new();
+        let config = Config::new().unwrap();
 
         user_mock_repo.expect_create().returning(|name, _, role| {
             Ok(SimpleUser {
@@ -64,6 +265,9 @@ mod test {
                 role,
                 created_at: Some(chrono::Utc::now()),
                 updated_at: Some(chrono::Utc::now()),
+                status: 0,
+                deleted_at: None,
+                service_account: false,
             })
         });
 
@@ -75,4 +279,218 @@ mod test {
         assert_eq!(created_user.name, "boris");
         assert_eq!(created_user.role, 0);
     }
+
+    #[test]
+    fn it_cannot_sign_up_with_role() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let req = serde_json::from_str::<CreateUserRequest>(
+            r#"{"name": "boris", "password": "Correct1", "role": 2}"#,
+        )
+        .unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let config = Config::new().unwrap();
+
+        user_mock_repo
+            .expect_create()
+            .withf(|_, _, role| *role == Role::User as i16)
+            .returning(|name, _, role| {
+                Ok(SimpleUser {
+                    id: uuid::Uuid::new_v4(),
+                    name: name.to_string(),
+                    role,
+                    created_at: Some(chrono::Utc::now()),
+                    updated_at: Some(chrono::Utc::now()),
+                    status: 0,
+                    deleted_at: None,
+                    service_account: false,
+                })
+            });
+
+        let response = runtime.block_on(create_user(req, Arc::new(user_mock_repo), &config));
+
+        assert_eq!(response.unwrap().role, 0);
+    }
+
+    fn simple_user(name: &str, updated_at: chrono::DateTime<chrono::Utc>) -> SimpleUser {
+        SimpleUser {
+            id: uuid::Uuid::new_v4(),
+            name: name.to_string(),
+            role: 0,
+            created_at: Some(updated_at),
+            updated_at: Some(updated_at),
+            status: 0,
+            deleted_at: None,
+            service_account: false,
+        }
+    }
+
+    #[test]
+    fn it_cannot_get_missing_user() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+
+        user_mock_repo.expect_get().returning(|_| Ok(None));
+
+        let response = runtime.block_on(get_user(Arc::new(user_mock_repo), &Uuid::new_v4()));
+
+        assert_eq!(response.unwrap_err(), AppError::UserNotExist);
+    }
+
+    #[test]
+    fn it_cannot_get_deleted_user() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+
+        user_mock_repo.expect_get().returning(|_| {
+            let mut user = simple_user("boris", chrono::Utc::now());
+            user.status = UserStatus::Deleted.into();
+            Ok(Some(user))
+        });
+
+        let response = runtime.block_on(get_user(Arc::new(user_mock_repo), &Uuid::new_v4()));
+
+        assert_eq!(response.unwrap_err(), AppError::UserNotExist);
+    }
+
+    #[test]
+    fn it_can_list_users_by_page() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let last_updated_at = chrono::Utc::now();
+
+        user_mock_repo
+            .expect_list()
+            .withf(|keyword, cursor, page_size| {
+                keyword.as_deref() == Some("bo") && cursor.is_none() && *page_size == 2
+            })
+            .returning(move |_, _, _| {
+                let mut never_updated = simple_user("bo", last_updated_at);
+                never_updated.updated_at = None;
+                Ok(vec![never_updated, simple_user("bob", last_updated_at)])
+            });
+
+        let query = ListUsersQuery {
+            keyword: Some("bo".to_string()),
+            updated_at: None,
+            id: None,
+            page_size: Some(2),
+        };
+        let page = runtime
+            .block_on(list_users(Arc::new(user_mock_repo), query))
+            .unwrap();
+
+        assert_eq!(page.users.len(), 2);
+        assert_eq!(page_updated_at(&page.users[0]).timestamp(), 0);
+        assert_eq!(page.next_updated_at, Some(last_updated_at));
+        assert_eq!(page.next_id, Some(page.users[1].id));
+    }
+
+    #[test]
+    fn it_cannot_rename_user_to_taken_name() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+
+        user_mock_repo
+            .expect_update()
+            .returning(|_, _, _| Err(AppError::UserAlreadyExist));
+
+        let req = UpdateUserRequest {
+            name: Some("boris".to_string()),
+            role: None,
+        };
+        let response = runtime.block_on(update_user(
+            Arc::new(user_mock_repo),
+            Arc::new(MockAuthRepository::new()),
+            &Uuid::new_v4(),
+            req,
+        ));
+
+        assert_eq!(response.unwrap_err(), AppError::UserAlreadyExist);
+    }
+
+    #[test]
+    fn it_can_change_role_and_revoke_sessions() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+        let id = Uuid::new_v4();
+
+        user_mock_repo
+            .expect_update()
+            .withf(|_, name, role| name.is_none() && *role == Some(Role::User as i16))
+            .returning(|_, _, role| {
+                let mut user = simple_user("boris", chrono::Utc::now());
+                user.role = role.unwrap();
+                Ok(Some(user))
+            });
+        auth_mock_repo
+            .expect_expire_all()
+            .withf(move |user_id| *user_id == id)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let req = UpdateUserRequest {
+            name: None,
+            role: Some(Role::User as i16),
+        };
+        let user = runtime
+            .block_on(update_user(Arc::new(user_mock_repo), Arc::new(auth_mock_repo), &id, req))
+            .unwrap();
+
+        assert_eq!(user.role, Role::User as i16);
+    }
+
+    #[test]
+    fn it_can_disable_user_and_revoke_its_sessions() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let mut auth_mock_repo = MockAuthRepository::new();
+        let id = Uuid::new_v4();
+        let claims = Claims::new(Uuid::new_v4().to_string(), 0, 2, "session".to_string());
+
+        user_mock_repo
+            .expect_set_status()
+            .withf(|_, status| *status == UserStatus::Disabled)
+            .returning(|_, status| {
+                let mut simple_user = simple_user("boris", chrono::Utc::now());
+                simple_user.status = status.into();
+                Ok(Some(simple_user))
+            });
+        auth_mock_repo
+            .expect_block_user()
+            .withf(move |user_id| *user_id == id)
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let response = runtime.block_on(change_status(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &claims,
+            &id,
+            UserStatus::Disabled,
+        ));
+
+        assert_eq!(response.unwrap().status, 1);
+    }
+
+    #[test]
+    fn it_cannot_disable_itself() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut user_mock_repo = MockUserRepository::new();
+        let auth_mock_repo = MockAuthRepository::new();
+        let id = Uuid::new_v4();
+        let claims = Claims::new(id.to_string(), 0, 2, "session".to_string());
+
+        user_mock_repo.expect_set_status().never();
+
+        let response = runtime.block_on(change_status(
+            Arc::new(user_mock_repo),
+            Arc::new(auth_mock_repo),
+            &claims,
+            &id,
+            UserStatus::Deleted,
+        ));
+
+        assert_eq!(response.unwrap_err(), AppError::Forbidden);
+    }
 }
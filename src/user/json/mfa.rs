@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Mfa {
+    pub user_id: uuid::Uuid,
+    pub secret: Vec<u8>,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnrollMfaResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+/// Shown once, only hashes of the codes are kept.
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
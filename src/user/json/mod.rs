@@ -1,3 +1,5 @@
+pub mod mfa;
+pub mod password;
 pub mod request;
 pub mod table;
 pub mod user;
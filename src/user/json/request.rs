@@ -1,8 +1,34 @@
 use serde::Deserialize;
 
+/// Anyone can sign up, so the role isn't taken from the request, a user
+/// manager sets it afterwards.
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
     pub name: String,
     pub password: String,
+}
+
+/// A service account has no password, it authenticates with api keys
+/// created for it by a user manager.
+#[derive(Debug, Deserialize)]
+pub struct CreateServiceAccountRequest {
+    pub name: String,
     pub role: i16,
 }
+
+/// Only the given fields are changed.
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateUserRequest {
+    pub name: Option<String>,
+    pub role: Option<i16>,
+}
+
+/// Users are listed by `updated_at` then `id`, the `updated_at` and `id` of
+/// the last user of a page are the cursor of the next page.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListUsersQuery {
+    pub keyword: Option<String>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub id: Option<uuid::Uuid>,
+    pub page_size: Option<usize>,
+}
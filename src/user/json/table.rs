@@ -9,4 +9,34 @@ pub enum Users {
     Role,
     CreatedAt,
     UpdatedAt,
+    Status,
+    DeletedAt,
+    ServiceAccount,
+}
+
+#[derive(Iden)]
+pub enum Identities {
+    Table,
+    Provider,
+    Subject,
+    UserId,
+    Email,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+pub enum UserMfa {
+    Table,
+    UserId,
+    Secret,
+    Enabled,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Iden)]
+pub enum RecoveryCodes {
+    Table,
+    UserId,
+    CodeHash,
 }
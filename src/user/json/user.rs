@@ -1,6 +1,32 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+/// Status of the numeric `users.status` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    Active = 0,
+    Disabled = 1,
+    Deleted = 2,
+}
+
+/// An unknown status is taken as deleted, so it never grants access.
+impl From<i16> for UserStatus {
+    fn from(value: i16) -> Self {
+        match value {
+            0 => UserStatus::Active,
+            1 => UserStatus::Disabled,
+            _ => UserStatus::Deleted,
+        }
+    }
+}
+
+impl From<UserStatus> for i16 {
+    fn from(status: UserStatus) -> Self {
+        status as i16
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
 pub struct SimpleUser {
     pub id: uuid::Uuid,
@@ -8,6 +34,9 @@ pub struct SimpleUser {
     pub role: i16,
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: i16,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub service_account: bool,
 }
 
 impl From<User> for SimpleUser {
@@ -18,6 +47,9 @@ impl From<User> for SimpleUser {
             role: user.role,
             created_at: user.created_at,
             updated_at: user.updated_at,
+            status: user.status,
+            deleted_at: user.deleted_at,
+            service_account: user.service_account,
         }
     }
 }
@@ -30,4 +62,15 @@ pub struct User {
     pub role: i16,
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
     pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: i16,
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub service_account: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    pub users: Vec<SimpleUser>,
+    /// `None` on the last page.
+    pub next_updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub next_id: Option<uuid::Uuid>,
 }
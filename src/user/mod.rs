@@ -2,3 +2,5 @@ pub mod handlers;
 pub mod json;
 pub mod repo;
 pub mod route;
+pub mod totp;
+pub mod validate;
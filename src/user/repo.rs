@@ -2,13 +2,14 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query::{Expr, Order, PostgresQueryBuilder, Query, Value};
 use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 
 use crate::core::error::AppError;
-use crate::user::json::table::Users;
-use crate::user::json::user::{SimpleUser, User};
+use crate::user::json::mfa::Mfa;
+use crate::user::json::table::{Identities, RecoveryCodes, UserMfa, Users};
+use crate::user::json::user::{SimpleUser, User, UserStatus};
 
 #[async_trait]
 pub trait UserRepository {
@@ -21,14 +22,116 @@ pub trait UserRepository {
 
     async fn get(&self, id: &uuid::Uuid) -> Result<Option<SimpleUser>, AppError>;
 
+    /// Deleted users aren't listed. The users come after `cursor`, ordered by
+    /// `updated_at` then `id`, see `page_updated_at`.
     async fn list(
         &self,
         keyword: Option<String>,
-        updated_at: Option<chrono::DateTime<chrono::Utc>>,
+        cursor: Option<(chrono::DateTime<chrono::Utc>, uuid::Uuid)>,
         page_size: usize,
     ) -> Result<Vec<SimpleUser>, AppError>;
 
+    /// Changes the given fields, returns `None` when the user doesn't exist
+    /// or is deleted.
+    async fn update(
+        &self,
+        id: &Uuid,
+        username: Option<&str>,
+        role: Option<i16>,
+    ) -> Result<Option<SimpleUser>, AppError>;
+
+    /// Returns `false` when the user doesn't exist.
+    async fn update_password(&self, id: &Uuid, password: &str) -> Result<bool, AppError>;
+
+    /// A deleted user keeps its row and name, `deleted_at` is set while the
+    /// status is deleted. Returns `None` when the user doesn't exist.
+    async fn set_status(
+        &self,
+        id: &Uuid,
+        status: UserStatus,
+    ) -> Result<Option<SimpleUser>, AppError>;
+
     async fn get_by_name(&self, username: &str) -> Result<Option<User>, AppError>;
+
+    async fn get_by_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<SimpleUser>, AppError>;
+
+    /// Creates a user without a usable password, linked to the identity
+    /// `subject` of the external `provider`.
+    async fn create_with_identity(
+        &self,
+        username: &str,
+        role: i16,
+        provider: &str,
+        subject: &str,
+        email: Option<String>,
+    ) -> Result<SimpleUser, AppError>;
+
+    /// Creates a user without a usable password, it only authenticates with
+    /// its api keys.
+    async fn create_service_account(
+        &self,
+        username: &str,
+        role: i16,
+    ) -> Result<SimpleUser, AppError>;
+
+    async fn get_mfa(&self, user_id: &Uuid) -> Result<Option<Mfa>, AppError>;
+
+    /// Replaces the secret by a new one which isn't enabled yet, the
+    /// recovery codes of the old secret are dropped.
+    async fn save_mfa_secret(&self, user_id: &Uuid, secret: &[u8]) -> Result<(), AppError>;
+
+    async fn enable_mfa(&self, user_id: &Uuid, code_hashes: Vec<String>) -> Result<(), AppError>;
+
+    async fn disable_mfa(&self, user_id: &Uuid) -> Result<(), AppError>;
+
+    /// Deletes the recovery code, returns `false` when it doesn't exist.
+    async fn consume_recovery_code(
+        &self,
+        user_id: &Uuid,
+        code_hash: &str,
+    ) -> Result<bool, AppError>;
+}
+
+// Not a valid argon2 hash, so users of external identities and service
+// accounts can't log in with a password.
+const EXTERNAL_PASSWORD: &str = "!";
+
+const UNIQUE_VIOLATION: &str = "23505";
+
+/// A taken name is the only unique violation of the users table.
+fn write_error(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::Database(e) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+            AppError::UserAlreadyExist
+        }
+        _ => AppError::DatabaseError,
+    }
+}
+
+// Users which were never updated are listed first.
+const PAGE_UPDATED_AT: &str = "COALESCE(\"updated_at\", 'epoch')";
+
+/// The `updated_at` of `user` in the order of `list`.
+pub fn page_updated_at(user: &SimpleUser) -> DateTime<Utc> {
+    user.updated_at.unwrap_or_else(|| DateTime::<Utc>::from(std::time::UNIX_EPOCH))
+}
+
+/// Matches names containing `keyword`, its `%` and `_` are matched
+/// literally with the default `\` escape of postgres.
+fn contains_pattern(keyword: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in keyword.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
 }
 
 #[derive(Clone)]
@@ -42,17 +145,14 @@ impl PostgresUserRepository {
             connection_pool: pool,
         }
     }
-}
 
-#[async_trait]
-impl UserRepository for PostgresUserRepository {
-    async fn create(
-        &self,
+    fn insert_user_sql(
         username: &str,
         password: &str,
         role: i16,
-    ) -> Result<SimpleUser, AppError> {
-        let sql = Query::insert()
+        service_account: bool,
+    ) -> String {
+        Query::insert()
             .into_table(Users::Table)
             .columns(vec![
                 Users::Id,
@@ -61,6 +161,7 @@ impl UserRepository for PostgresUserRepository {
                 Users::Role,
                 Users::CreatedAt,
                 Users::UpdatedAt,
+                Users::ServiceAccount,
             ])
             .values_panic(vec![
                 uuid::Uuid::new_v4().into(),
@@ -69,6 +170,7 @@ impl UserRepository for PostgresUserRepository {
                 role.into(),
                 chrono::Utc::now().into(),
                 chrono::Utc::now().into(),
+                service_account.into(),
             ])
             .to_owned()
             .returning(
@@ -79,21 +181,30 @@ impl UserRepository for PostgresUserRepository {
                         Users::Role,
                         Users::CreatedAt,
                         Users::UpdatedAt,
+                        Users::Status,
+                        Users::DeletedAt,
+                        Users::ServiceAccount,
                     ])
                     .take(),
             )
-            .to_string(PostgresQueryBuilder);
+            .to_string(PostgresQueryBuilder)
+    }
+}
 
-        dbg!(&sql);
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn create(
+        &self,
+        username: &str,
+        password: &str,
+        role: i16,
+    ) -> Result<SimpleUser, AppError> {
+        let sql = Self::insert_user_sql(username, password, role, false);
 
-        let simple_user = sqlx::query_as::<_, SimpleUser>(sql.as_str())
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
             .fetch_one(&*self.connection_pool)
             .await
-            .map_err(|_| AppError::DatabaseError);
-
-        dbg!(&simple_user);
-
-        simple_user
+            .map_err(write_error)
     }
 
     async fn get(&self, id: &Uuid) -> Result<Option<SimpleUser>, AppError> {
@@ -104,27 +215,24 @@ impl UserRepository for PostgresUserRepository {
                 Users::Role,
                 Users::CreatedAt,
                 Users::UpdatedAt,
+                Users::Status,
+                Users::DeletedAt,
+                Users::ServiceAccount,
             ])
             .from(Users::Table)
             .and_where(Expr::col(Users::Id).eq(id.to_string()))
             .to_string(PostgresQueryBuilder);
 
-        dbg!(&sql);
-
-        let simple_user = sqlx::query_as::<_, SimpleUser>(sql.as_str())
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
             .fetch_optional(&*self.connection_pool)
             .await
-            .map_err(|_| AppError::DatabaseError);
-
-        dbg!(&simple_user);
-
-        simple_user
+            .map_err(|_| AppError::DatabaseError)
     }
 
     async fn list(
         &self,
         keyword: Option<String>,
-        updated_at: Option<DateTime<Utc>>,
+        cursor: Option<(DateTime<Utc>, Uuid)>,
         page_size: usize,
     ) -> Result<Vec<SimpleUser>, AppError> {
         let sql = Query::select()
@@ -134,25 +242,128 @@ impl UserRepository for PostgresUserRepository {
                 Users::Role,
                 Users::CreatedAt,
                 Users::UpdatedAt,
+                Users::Status,
+                Users::DeletedAt,
+                Users::ServiceAccount,
             ])
             .and_where_option(
-                keyword.map(|e| Expr::col(Users::Name).like(format!("%{}%", e).as_str())),
+                keyword.map(|e| Expr::col(Users::Name).like(contains_pattern(&e).as_str())),
             )
-            .and_where_option(updated_at.map(|e| Expr::col(Users::UpdatedAt).gt(e)))
+            .and_where_option(cursor.map(|(updated_at, id)| {
+                Expr::cust_with_values(
+                    format!("({}, \"id\") > (?, ?)", PAGE_UPDATED_AT).as_str(),
+                    vec![Value::from(updated_at), Value::from(id)],
+                )
+            }))
+            .and_where(Expr::col(Users::Status).ne(i16::from(UserStatus::Deleted)))
             .from(Users::Table)
+            .order_by_expr(Expr::cust(PAGE_UPDATED_AT), Order::Asc)
+            .order_by(Users::Id, Order::Asc)
             .limit(page_size as u64)
             .to_string(PostgresQueryBuilder);
 
-        dbg!(&sql);
-
-        let simple_users = sqlx::query_as::<_, SimpleUser>(sql.as_str())
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
             .fetch_all(&*self.connection_pool)
             .await
-            .map_err(|_| AppError::DatabaseError);
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn update(
+        &self,
+        id: &Uuid,
+        username: Option<&str>,
+        role: Option<i16>,
+    ) -> Result<Option<SimpleUser>, AppError> {
+        let mut values = vec![(Users::UpdatedAt, chrono::Utc::now().into())];
+        if let Some(username) = username {
+            values.push((Users::Name, username.into()));
+        }
+        if let Some(role) = role {
+            values.push((Users::Role, role.into()));
+        }
+
+        let sql = Query::update()
+            .table(Users::Table)
+            .values(values)
+            .and_where(Expr::col(Users::Id).eq(id.to_string()))
+            .and_where(Expr::col(Users::Status).ne(i16::from(UserStatus::Deleted)))
+            .returning(
+                Query::select()
+                    .columns(vec![
+                        Users::Id,
+                        Users::Name,
+                        Users::Role,
+                        Users::CreatedAt,
+                        Users::UpdatedAt,
+                        Users::Status,
+                        Users::DeletedAt,
+                        Users::ServiceAccount,
+                    ])
+                    .take(),
+            )
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
+            .fetch_optional(&*self.connection_pool)
+            .await
+            .map_err(write_error)
+    }
 
-        dbg!(&simple_users);
+    async fn update_password(&self, id: &Uuid, password: &str) -> Result<bool, AppError> {
+        let sql = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::Password, password.into()),
+                (Users::UpdatedAt, chrono::Utc::now().into()),
+            ])
+            .and_where(Expr::col(Users::Id).eq(id.to_string()))
+            .to_string(PostgresQueryBuilder);
 
-        simple_users
+        sqlx::query(sql.as_str())
+            .execute(&*self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() == 1)
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn set_status(
+        &self,
+        id: &Uuid,
+        status: UserStatus,
+    ) -> Result<Option<SimpleUser>, AppError> {
+        let deleted_at = match status {
+            UserStatus::Deleted => Some(chrono::Utc::now()),
+            _ => None,
+        };
+
+        let sql = Query::update()
+            .table(Users::Table)
+            .values(vec![
+                (Users::Status, i16::from(status).into()),
+                (Users::DeletedAt, deleted_at.into()),
+                (Users::UpdatedAt, chrono::Utc::now().into()),
+            ])
+            .and_where(Expr::col(Users::Id).eq(id.to_string()))
+            .returning(
+                Query::select()
+                    .columns(vec![
+                        Users::Id,
+                        Users::Name,
+                        Users::Role,
+                        Users::CreatedAt,
+                        Users::UpdatedAt,
+                        Users::Status,
+                        Users::DeletedAt,
+                        Users::ServiceAccount,
+                    ])
+                    .take(),
+            )
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
+            .fetch_optional(&*self.connection_pool)
+            .await
+            .map_err(|_| AppError::DatabaseError)
     }
 
     async fn get_by_name(&self, username: &str) -> Result<Option<User>, AppError> {
@@ -164,20 +375,274 @@ impl UserRepository for PostgresUserRepository {
                 Users::Role,
                 Users::CreatedAt,
                 Users::UpdatedAt,
+                Users::Status,
+                Users::DeletedAt,
+                Users::ServiceAccount,
             ])
             .from(Users::Table)
             .and_where(Expr::col(Users::Name).eq(username))
             .to_string(PostgresQueryBuilder);
 
-        dbg!(&sql);
+        sqlx::query_as::<_, User>(sql.as_str())
+            .fetch_optional(&*self.connection_pool)
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
 
-        let user = sqlx::query_as::<_, User>(sql.as_str())
+    async fn get_by_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<SimpleUser>, AppError> {
+        let sql = Query::select()
+            .columns(vec![
+                (Users::Table, Users::Id),
+                (Users::Table, Users::Name),
+                (Users::Table, Users::Role),
+                (Users::Table, Users::CreatedAt),
+                (Users::Table, Users::UpdatedAt),
+                (Users::Table, Users::Status),
+                (Users::Table, Users::DeletedAt),
+                (Users::Table, Users::ServiceAccount),
+            ])
+            .from(Users::Table)
+            .inner_join(
+                Identities::Table,
+                Expr::tbl(Identities::Table, Identities::UserId).equals(Users::Table, Users::Id),
+            )
+            .and_where(Expr::tbl(Identities::Table, Identities::Provider).eq(provider))
+            .and_where(Expr::tbl(Identities::Table, Identities::Subject).eq(subject))
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
             .fetch_optional(&*self.connection_pool)
             .await
-            .map_err(|_| AppError::DatabaseError);
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn create_with_identity(
+        &self,
+        username: &str,
+        role: i16,
+        provider: &str,
+        subject: &str,
+        email: Option<String>,
+    ) -> Result<SimpleUser, AppError> {
+        let mut transaction = self
+            .connection_pool
+            .begin()
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        let sql = Self::insert_user_sql(username, EXTERNAL_PASSWORD, role, false);
+
+        let simple_user = sqlx::query_as::<_, SimpleUser>(sql.as_str())
+            .fetch_one(&mut transaction)
+            .await
+            .map_err(write_error)?;
+
+        let sql = Query::insert()
+            .into_table(Identities::Table)
+            .columns(vec![
+                Identities::Provider,
+                Identities::Subject,
+                Identities::UserId,
+                Identities::Email,
+                Identities::CreatedAt,
+            ])
+            .values_panic(vec![
+                provider.into(),
+                subject.into(),
+                simple_user.id.into(),
+                email.into(),
+                chrono::Utc::now().into(),
+            ])
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query(sql.as_str())
+            .execute(&mut transaction)
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        Ok(simple_user)
+    }
+
+    async fn create_service_account(
+        &self,
+        username: &str,
+        role: i16,
+    ) -> Result<SimpleUser, AppError> {
+        let sql = Self::insert_user_sql(username, EXTERNAL_PASSWORD, role, true);
+
+        sqlx::query_as::<_, SimpleUser>(sql.as_str())
+            .fetch_one(&*self.connection_pool)
+            .await
+            .map_err(write_error)
+    }
+
+    async fn get_mfa(&self, user_id: &Uuid) -> Result<Option<Mfa>, AppError> {
+        let sql = Query::select()
+            .columns(vec![UserMfa::UserId, UserMfa::Secret, UserMfa::Enabled])
+            .from(UserMfa::Table)
+            .and_where(Expr::col(UserMfa::UserId).eq(user_id.to_string()))
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query_as::<_, Mfa>(sql.as_str())
+            .fetch_optional(&*self.connection_pool)
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn save_mfa_secret(&self, user_id: &Uuid, secret: &[u8]) -> Result<(), AppError> {
+        let mut transaction = self
+            .connection_pool
+            .begin()
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        let statements = vec![
+            Query::delete()
+                .from_table(RecoveryCodes::Table)
+                .and_where(Expr::col(RecoveryCodes::UserId).eq(user_id.to_string()))
+                .to_string(PostgresQueryBuilder),
+            Query::delete()
+                .from_table(UserMfa::Table)
+                .and_where(Expr::col(UserMfa::UserId).eq(user_id.to_string()))
+                .to_string(PostgresQueryBuilder),
+            Query::insert()
+                .into_table(UserMfa::Table)
+                .columns(vec![
+                    UserMfa::UserId,
+                    UserMfa::Secret,
+                    UserMfa::Enabled,
+                    UserMfa::CreatedAt,
+                    UserMfa::UpdatedAt,
+                ])
+                .values_panic(vec![
+                    (*user_id).into(),
+                    secret.to_vec().into(),
+                    false.into(),
+                    chrono::Utc::now().into(),
+                    chrono::Utc::now().into(),
+                ])
+                .to_string(PostgresQueryBuilder),
+        ];
+
+        for sql in statements {
+            sqlx::query(sql.as_str())
+                .execute(&mut transaction)
+                .await
+                .map_err(|_| AppError::DatabaseError)?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn enable_mfa(&self, user_id: &Uuid, code_hashes: Vec<String>) -> Result<(), AppError> {
+        let mut transaction = self
+            .connection_pool
+            .begin()
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        let sql = Query::update()
+            .table(UserMfa::Table)
+            .values(vec![
+                (UserMfa::Enabled, true.into()),
+                (UserMfa::UpdatedAt, chrono::Utc::now().into()),
+            ])
+            .and_where(Expr::col(UserMfa::UserId).eq(user_id.to_string()))
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query(sql.as_str())
+            .execute(&mut transaction)
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        let mut insert = Query::insert();
+        insert
+            .into_table(RecoveryCodes::Table)
+            .columns(vec![RecoveryCodes::UserId, RecoveryCodes::CodeHash]);
+        for code_hash in code_hashes {
+            insert.values_panic(vec![(*user_id).into(), code_hash.into()]);
+        }
+
+        sqlx::query(insert.to_string(PostgresQueryBuilder).as_str())
+            .execute(&mut transaction)
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn disable_mfa(&self, user_id: &Uuid) -> Result<(), AppError> {
+        let mut transaction = self
+            .connection_pool
+            .begin()
+            .await
+            .map_err(|_| AppError::DatabaseError)?;
+
+        let statements = vec![
+            Query::delete()
+                .from_table(RecoveryCodes::Table)
+                .and_where(Expr::col(RecoveryCodes::UserId).eq(user_id.to_string()))
+                .to_string(PostgresQueryBuilder),
+            Query::delete()
+                .from_table(UserMfa::Table)
+                .and_where(Expr::col(UserMfa::UserId).eq(user_id.to_string()))
+                .to_string(PostgresQueryBuilder),
+        ];
+
+        for sql in statements {
+            sqlx::query(sql.as_str())
+                .execute(&mut transaction)
+                .await
+                .map_err(|_| AppError::DatabaseError)?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .map_err(|_| AppError::DatabaseError)
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        user_id: &Uuid,
+        code_hash: &str,
+    ) -> Result<bool, AppError> {
+        let sql = Query::delete()
+            .from_table(RecoveryCodes::Table)
+            .and_where(Expr::col(RecoveryCodes::UserId).eq(user_id.to_string()))
+            .and_where(Expr::col(RecoveryCodes::CodeHash).eq(code_hash))
+            .to_string(PostgresQueryBuilder);
+
+        sqlx::query(sql.as_str())
+            .execute(&*self.connection_pool)
+            .await
+            .map(|result| result.rows_affected() == 1)
+            .map_err(|_| AppError::DatabaseError)
+    }
+}
 
-        dbg!(&user);
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        user
+    #[test]
+    fn it_can_match_wildcards_of_keyword_literally() {
+        assert_eq!(contains_pattern("bo"), "%bo%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
     }
 }
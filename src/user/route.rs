@@ -1,17 +1,153 @@
+use uuid::Uuid;
 use warp::{Filter, Reply};
 use warp::filters::BoxedFilter;
 
+use crate::auth::role::Permission;
+use crate::core::middlewares::authorization::{authenticated, authorized, Access, AuthMethods};
 use crate::core::middlewares::with_env::with_env;
 use crate::Environment;
-use crate::user::handlers::v1::create_user_handler;
+use crate::user::handlers::mfa::{disable_mfa_handler, enroll_mfa_handler, verify_mfa_handler};
+use crate::user::handlers::password::{
+    change_password_handler, forgot_password_handler, reset_password_handler,
+};
+use crate::user::handlers::v1::{
+    activate_user_handler, create_service_account_handler, create_user_handler,
+    delete_user_handler, disable_user_handler, get_user_handler, list_users_handler,
+    update_user_handler,
+};
 
 pub fn routes(env: Environment) -> BoxedFilter<(impl Reply,)> {
     let login_route = warp::path!("api" / "v1" / "users")
         .and(warp::post())
         .and(warp::body::json())
-        .and(with_env(env))
+        .and(with_env(env.clone()))
         .and_then(create_user_handler);
 
-    let routes = login_route;
+    let create_service_account_route = warp::path!("api" / "v1" / "service-accounts")
+        .and(warp::post())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(create_service_account_handler);
+
+    let get_user_route = warp::path!("api" / "v1" / "users" / Uuid)
+        .and(warp::get())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ReadUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(get_user_handler);
+
+    let list_users_route = warp::path!("api" / "v1" / "users")
+        .and(warp::get())
+        .and(warp::query())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ReadUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(list_users_handler);
+
+    let update_user_route = warp::path!("api" / "v1" / "users" / Uuid)
+        .and(warp::patch())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(update_user_handler);
+
+    let delete_user_route = warp::path!("api" / "v1" / "users" / Uuid)
+        .and(warp::delete())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(delete_user_handler);
+
+    let disable_user_route = warp::path!("api" / "v1" / "users" / Uuid / "disable")
+        .and(warp::post())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(disable_user_handler);
+
+    let activate_user_route = warp::path!("api" / "v1" / "users" / Uuid / "activate")
+        .and(warp::post())
+        .and(authorized(
+            env.clone(),
+            AuthMethods::all(),
+            Access::permission(Permission::ManageUsers),
+        ))
+        .and(with_env(env.clone()))
+        .and_then(activate_user_handler);
+
+    let change_password_route = warp::path!("api" / "v1" / "users" / "me" / "password")
+        .and(warp::post())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(change_password_handler);
+
+    let forgot_password_route = warp::path!("api" / "v1" / "password" / "forgot")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(forgot_password_handler);
+
+    let reset_password_route = warp::path!("api" / "v1" / "password" / "reset")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(reset_password_handler);
+
+    let enroll_mfa_route = warp::path!("api" / "v1" / "users" / "me" / "mfa")
+        .and(warp::post())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(with_env(env.clone()))
+        .and_then(enroll_mfa_handler);
+
+    let verify_mfa_route = warp::path!("api" / "v1" / "users" / "me" / "mfa" / "verify")
+        .and(warp::post())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(warp::body::json())
+        .and(with_env(env.clone()))
+        .and_then(verify_mfa_handler);
+
+    let disable_mfa_route = warp::path!("api" / "v1" / "users" / "me" / "mfa")
+        .and(warp::delete())
+        .and(authenticated(env.clone(), AuthMethods::session()))
+        .and(warp::body::json())
+        .and(with_env(env))
+        .and_then(disable_mfa_handler);
+
+    let routes = login_route
+        .or(create_service_account_route)
+        .or(get_user_route)
+        .or(list_users_route)
+        .or(update_user_route)
+        .or(delete_user_route)
+        .or(disable_user_route)
+        .or(activate_user_route)
+        .or(change_password_route)
+        .or(forgot_password_route)
+        .or(reset_password_route)
+        .or(enroll_mfa_route)
+        .or(verify_mfa_route)
+        .or(disable_mfa_route);
     routes.boxed()
 }
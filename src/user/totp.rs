@@ -0,0 +1,154 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::core::util::constant_time_eq;
+
+// Time-based one-time passwords (RFC 6238) as generated by authenticator
+// apps, 6 digits with a 30 seconds step.
+const DIGITS: u32 = 6;
+const STEP_SECONDS: u64 = 30;
+/// Codes of the previous and the next step are accepted, so a code typed
+/// at the end of its step or a drifting phone clock still works.
+const ALLOWED_DRIFT: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+// A dump of the hashes can't be brute forced with 80 bits.
+const RECOVERY_CODE_BYTES: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Base32 without padding, the encoding authenticator apps expect.
+pub fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let label = format!("{}:{}", issuer, account);
+    let query = [
+        ("secret", base32(secret)),
+        ("issuer", issuer.to_string()),
+        ("digits", DIGITS.to_string()),
+        ("period", STEP_SECONDS.to_string()),
+    ];
+
+    let mut url = reqwest::Url::parse("otpauth://totp/").expect("Can't parse the otpauth url.");
+    url.set_path(label.as_str());
+    url.query_pairs_mut().extend_pairs(query.iter());
+    url.to_string()
+}
+
+pub fn code_at(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC can take key of any size.");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation of RFC 4226.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!("{:0width$}", value % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+/// Returns the step the code belongs to, so the caller can refuse a code
+/// which was already used.
+pub fn verify(secret: &[u8], code: &str, timestamp: u64) -> Option<u64> {
+    let current = timestamp / STEP_SECONDS;
+
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+        .find(|step| constant_time_eq(code_at(secret, *step).as_bytes(), code.as_bytes()))
+}
+
+/// 80 random bits in groups of 4 characters, such as
+/// `mzxw-6ytb-oi4d-2nbv`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            base32(&bytes)
+                .to_lowercase()
+                .as_bytes()
+                .chunks(4)
+                .map(|group| String::from_utf8_lossy(group).into_owned())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough for a plain hash, only the hash is
+/// stored. The case, spaces and dashes of the code don't matter.
+pub fn hash_recovery_code(code: &str) -> String {
+    let code = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_lowercase();
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_can_generate_rfc_6238_codes() {
+        let secret = b"12345678901234567890";
+
+        assert_eq!(code_at(secret, 59 / STEP_SECONDS), "287082");
+        assert_eq!(code_at(secret, 1111111109 / STEP_SECONDS), "081804");
+        assert_eq!(verify(secret, "081804", 1111111109 + STEP_SECONDS), Some(37037036));
+        assert_eq!(verify(secret, "081804", 1111111109 + 3 * STEP_SECONDS), None);
+    }
+
+    #[test]
+    fn it_can_encode_base32() {
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32(b"12345678901234567890"), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn it_can_hash_recovery_code_loosely() {
+        assert_eq!(hash_recovery_code(" ABCD2345 "), hash_recovery_code("abcd2345"));
+        assert_eq!(hash_recovery_code("ABCD-2345"), hash_recovery_code("abcd2345"));
+    }
+
+    #[test]
+    fn it_can_generate_80_bit_recovery_codes() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in codes {
+            assert_eq!(code.len(), 19);
+            assert_eq!(code.replace('-', "").len() * 5, RECOVERY_CODE_BYTES * 8);
+        }
+    }
+}
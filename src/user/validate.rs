@@ -0,0 +1,166 @@
+use std::convert::TryFrom;
+
+use crate::auth::role::Role;
+use crate::core::error::{AppError, FieldError};
+use crate::user::json::request::{
+    CreateServiceAccountRequest, CreateUserRequest, UpdateUserRequest,
+};
+
+// `users.name` is a varchar(64).
+const MIN_NAME_LENGTH: usize = 3;
+const MAX_NAME_LENGTH: usize = 64;
+const MIN_PASSWORD_LENGTH: usize = 8;
+// Long passwords only cost hashing time.
+const MAX_PASSWORD_LENGTH: usize = 128;
+const MIN_PASSWORD_CHARACTER_CLASSES: usize = 2;
+
+impl CreateUserRequest {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let errors = [
+            validate_name(self.name.as_str()),
+            validate_password(self.password.as_str()),
+        ];
+
+        collect(errors)
+    }
+}
+
+impl CreateServiceAccountRequest {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let errors = [validate_name(self.name.as_str()), validate_role(self.role)];
+
+        collect(errors)
+    }
+}
+
+impl UpdateUserRequest {
+    pub fn validate(&self) -> Result<(), AppError> {
+        let errors = [
+            self.name.as_deref().and_then(validate_name),
+            self.role.and_then(validate_role),
+        ];
+
+        collect(errors)
+    }
+}
+
+/// Validates a new password of a password change or reset.
+pub fn check_password(password: &str) -> Result<(), AppError> {
+    collect([validate_password(password)])
+}
+
+fn collect<const N: usize>(errors: [Option<FieldError>; N]) -> Result<(), AppError> {
+    let errors: Vec<FieldError> = errors.into_iter().flatten().collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::ValidationFailed(errors))
+    }
+}
+
+/// Names of local users are plain ascii, `:` is left to the names of
+/// external users.
+fn validate_name(name: &str) -> Option<FieldError> {
+    let length = name.chars().count();
+
+    if !(MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&length) {
+        return Some(FieldError::new(
+            "name",
+            format!("must be {} to {} characters.", MIN_NAME_LENGTH, MAX_NAME_LENGTH),
+        ));
+    }
+
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        return Some(FieldError::new(
+            "name",
+            "may only contain letters, digits, '.', '_' and '-'.",
+        ));
+    }
+
+    None
+}
+
+fn validate_password(password: &str) -> Option<FieldError> {
+    let length = password.chars().count();
+
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Some(FieldError::new(
+            "password",
+            format!(
+                "must be {} to {} characters.",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+            ),
+        ));
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|class| **class).count() < MIN_PASSWORD_CHARACTER_CLASSES {
+        return Some(FieldError::new(
+            "password",
+            "must mix letters with digits, symbols or letters of the other case.",
+        ));
+    }
+
+    None
+}
+
+fn validate_role(role: i16) -> Option<FieldError> {
+    Role::try_from(role)
+        .err()
+        .map(|_| FieldError::new("role", "must be 0 (user), 1 (staff) or 2 (admin)."))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(name: &str, password: &str) -> CreateUserRequest {
+        CreateUserRequest {
+            name: name.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn fields(result: Result<(), AppError>) -> Vec<String> {
+        match result {
+            Err(AppError::ValidationFailed(errors)) => {
+                errors.into_iter().map(|error| error.field).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn it_can_validate_create_user_request() {
+        assert!(request("boris", "Correct1").validate().is_ok());
+        assert!(request("boris.lok", "correct horse").validate().is_ok());
+    }
+
+    #[test]
+    fn it_can_report_every_invalid_field() {
+        assert_eq!(fields(request("bo", "123").validate()), vec!["name", "password"]);
+        assert_eq!(fields(request("corporate:boris", "Correct1").validate()), vec!["name"]);
+        assert_eq!(fields(request(&"b".repeat(65), "Correct1").validate()), vec!["name"]);
+        assert_eq!(fields(request("boris", "alllowercase").validate()), vec!["password"]);
+    }
+
+    #[test]
+    fn it_can_validate_partial_update() {
+        let req = UpdateUserRequest {
+            name: None,
+            role: Some(3),
+        };
+
+        assert_eq!(fields(req.validate()), vec!["role"]);
+        assert!(UpdateUserRequest::default().validate().is_ok());
+    }
+}